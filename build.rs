@@ -0,0 +1,4 @@
+fn main() {
+    println!("cargo:rerun-if-changed=proto/events.proto");
+    prost_build::compile_protos(&["proto/events.proto"], &["proto/"]).unwrap();
+}
@@ -11,6 +11,9 @@ pub trait ChainCache: Send {
     async fn exists(&mut self, block_id: BlockId) -> bool;
     async fn get_best_block(&mut self) -> Option<BlockRecord>;
     async fn take_best_block(&mut self) -> Option<Block>;
+    /// Get the block with the given ID, without removing it from the cache.
+    async fn get_block(&mut self, block_id: BlockId) -> Option<Block>;
+    async fn get_parent_id(&mut self, block_id: BlockId) -> Option<BlockId>;
 }
 
 pub struct InMemoryCache {
@@ -62,6 +65,14 @@ impl ChainCache for InMemoryCache {
         }
         None
     }
+
+    async fn get_block(&mut self, block_id: BlockId) -> Option<Block> {
+        self.blocks.get(&block_id.0).cloned()
+    }
+
+    async fn get_parent_id(&mut self, block_id: BlockId) -> Option<BlockId> {
+        self.blocks.get(&block_id.0).map(|blk| blk.parent_id)
+    }
 }
 
 #[async_trait]
@@ -81,4 +92,12 @@ impl ChainCache for Box<dyn ChainCache> {
     async fn take_best_block(&mut self) -> Option<Block> {
         (**self).take_best_block().await
     }
+
+    async fn get_block(&mut self, block_id: BlockId) -> Option<Block> {
+        (**self).get_block(block_id).await
+    }
+
+    async fn get_parent_id(&mut self, block_id: BlockId) -> Option<BlockId> {
+        (**self).get_parent_id(block_id).await
+    }
 }
@@ -1,9 +1,15 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use async_std::task::spawn_blocking;
 use async_trait::async_trait;
-use ergo_lib::{chain::transaction::TxId, ergo_chain_types::BlockId};
+use ergo_lib::{
+    chain::transaction::{DataInput, TxId, TxIoVec},
+    ergo_chain_types::{BlockId, Header},
+    ergotree_ir::chain::ergo_box::ErgoBox,
+};
 
+use crate::client::model::{BlockInput, BlockTransaction};
 use crate::constants::ERGO_MAX_ROLLBACK_DEPTH;
 use crate::model::{Block, BlockRecord};
 use crate::rocksdb::RocksConfig;
@@ -12,6 +18,11 @@ use super::chain_cache::ChainCache;
 
 static BEST_BLOCK: &str = "BEST_BLOCK";
 static OLDEST_BLOCK: &str = "OLDEST_BLOCK";
+static LAYOUT_VERSION: &str = "LAYOUT_VERSION";
+
+/// Version of the encoding of cached transactions, stored under {LAYOUT_VERSION}. Caches
+/// written before inputs carried their spending proofs have no version stored.
+const CURRENT_LAYOUT_VERSION: u32 = 1;
 
 /// Given a block `B`, let `HB` denote the (lowercase) hex-representation of block's ID. Then
 ///  - {HB}:p is the key which maps to the hex-representation of B's parent block ID.
@@ -22,10 +33,15 @@ static OLDEST_BLOCK: &str = "OLDEST_BLOCK";
 ///    `HT` of the transaction ID of every transaction of `B`.
 ///    - Every {HT} is a key which maps to the Ergo-binary-encoded representation of its
 ///      transaction.
+///  - {HB}:hd is the key which maps to the JSON-encoded header and size of `B`, if known.
 ///  - {BEST_BLOCK} is a key which maps to a `BlockRecord` instance associated with the most
 ///    recently-stored block.
 ///  - {OLDEST_BLOCK} is a key which maps to a `BlockRecord` instance associated with the oldest
 ///    block in the persistent store.
+///  - {LAYOUT_VERSION} is a key which maps to the version of the encoding of transactions.
+///
+/// Clones share the underlying database.
+#[derive(Clone)]
 pub struct ChainCacheRocksDB {
     pub db: Arc<rocksdb::OptimisticTransactionDB>,
     /// Represents the maximum number of blocks in the persistent store.
@@ -33,14 +49,92 @@ pub struct ChainCacheRocksDB {
 }
 
 impl ChainCacheRocksDB {
+    /// Opens the cache, migrating transactions stored in an older layout.
     pub fn new(conf: RocksConfig) -> Self {
+        let db = rocksdb::OptimisticTransactionDB::open_default(conf.db_path).unwrap();
+        migrate_layout(&db);
         Self {
-            db: Arc::new(rocksdb::OptimisticTransactionDB::open_default(conf.db_path).unwrap()),
+            db: Arc::new(db),
             max_rollback_depth: ERGO_MAX_ROLLBACK_DEPTH,
         }
     }
 }
 
+/// Layout transactions were cached in before inputs carried their spending proofs.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct LegacyBlockTransaction {
+    id: TxId,
+    inputs: TxIoVec<ErgoBox>,
+    data_inputs: Option<TxIoVec<DataInput>>,
+    outputs: TxIoVec<ErgoBox>,
+}
+
+impl From<LegacyBlockTransaction> for BlockTransaction {
+    fn from(tx: LegacyBlockTransaction) -> Self {
+        Self {
+            id: tx.id,
+            inputs: tx.inputs.mapped(|ergo_box| BlockInput {
+                ergo_box,
+                spending_proof: None,
+            }),
+            data_inputs: tx.data_inputs,
+            outputs: tx.outputs,
+        }
+    }
+}
+
+/// Bincode isn't self-describing, so transactions of a cache without a stored layout version
+/// are re-encoded up front, walking back from the best block, rather than told apart on read.
+fn migrate_layout(db: &rocksdb::OptimisticTransactionDB) {
+    let version_key = bincode::serialize(LAYOUT_VERSION).unwrap();
+    if let Some(bytes) = db.get(&version_key).unwrap() {
+        let version: u32 = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(
+            version, CURRENT_LAYOUT_VERSION,
+            "Unsupported chain cache layout version {}",
+            version
+        );
+        return;
+    }
+    let db_tx = db.transaction();
+    let mut migrated = HashSet::new();
+    let mut next_block = db_tx
+        .get(bincode::serialize(BEST_BLOCK).unwrap())
+        .unwrap()
+        .map(|bytes| bincode::deserialize::<BlockRecord>(&bytes).unwrap().id);
+    while let Some(id) = next_block {
+        let Some(tx_ids_bytes) = db_tx.get(postfixed_key(&id, TRANSACTION_POSTFIX)).unwrap() else {
+            break;
+        };
+        let tx_ids: Vec<TxId> = bincode::deserialize(&tx_ids_bytes).unwrap();
+        for tx_id in tx_ids {
+            let tx_key = bincode::serialize(&tx_id).unwrap();
+            if !migrated.insert(tx_key.clone()) {
+                continue;
+            }
+            if let Some(tx_bytes) = db_tx.get(&tx_key).unwrap() {
+                let tx = BlockTransaction::from(
+                    bincode::deserialize::<LegacyBlockTransaction>(&tx_bytes).unwrap(),
+                );
+                db_tx
+                    .put(&tx_key, bincode::serialize(&tx).unwrap())
+                    .unwrap();
+            }
+        }
+        next_block = db_tx
+            .get(postfixed_key(&id, PARENT_POSTFIX))
+            .unwrap()
+            .map(|bytes| bincode::deserialize(&bytes).unwrap());
+    }
+    db_tx
+        .put(
+            &version_key,
+            bincode::serialize(&CURRENT_LAYOUT_VERSION).unwrap(),
+        )
+        .unwrap();
+    db_tx.commit().unwrap();
+}
+
 /// The Rocksdb bindings are not async, so we must wrap any uses of the library in
 /// `async_std::task::spawn_blocking`.
 #[async_trait]
@@ -69,6 +163,15 @@ impl ChainCache for ChainCacheRocksDB {
                     bincode::serialize(&block.height).unwrap(),
                 )
                 .unwrap();
+            // Headers are JSON-encoded, the representation their serde impls are made for.
+            if let Some(header) = &block.header {
+                db_tx
+                    .put(
+                        &postfixed_key(&block.id, HEADER_POSTFIX),
+                        serde_json::to_vec(&(header, block.size)).unwrap(),
+                    )
+                    .unwrap();
+            }
 
             let tx_ids: Vec<TxId> = block.transactions.iter().map(|t| t.id).collect();
             // We package together all transactions ids into a Vec.
@@ -149,6 +252,9 @@ impl ChainCache for ChainCacheRocksDB {
                     db_tx
                         .delete(postfixed_key(&oldest_id, CHILD_POSTFIX))
                         .unwrap();
+                    db_tx
+                        .delete(postfixed_key(&oldest_id, HEADER_POSTFIX))
+                        .unwrap();
                 }
             } else {
                 // This is the very first block to add to the store
@@ -226,6 +332,10 @@ impl ChainCache for ChainCacheRocksDB {
                             .unwrap();
                         let parent_id: BlockId = bincode::deserialize(&parent_id_bytes).unwrap();
 
+                        let header_key = postfixed_key(&id, HEADER_POSTFIX);
+                        let (header, size) = decode_header(db_tx.get(&header_key).unwrap());
+                        db_tx.delete(&header_key).unwrap();
+
                         db_tx.delete(&best_block_key).unwrap();
 
                         // The new best block will now be the parent of the old best block, if the parent
@@ -259,8 +369,11 @@ impl ChainCache for ChainCacheRocksDB {
                                     id,
                                     parent_id,
                                     height,
-                                    timestamp: 0, // todo: DEV-573
+                                    // Unknown for blocks cached without their header.
+                                    timestamp: header.as_ref().map_or(0, |h| h.timestamp),
                                     transactions,
+                                    header,
+                                    size,
                                 });
                             }
                             Err(e) => {
@@ -281,6 +394,61 @@ impl ChainCache for ChainCacheRocksDB {
         })
         .await
     }
+
+    async fn get_block(&mut self, block_id: BlockId) -> Option<Block> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let tx_ids_bytes = db
+                .get(postfixed_key(&block_id, TRANSACTION_POSTFIX))
+                .unwrap()?;
+            let tx_ids: Vec<TxId> = bincode::deserialize(&tx_ids_bytes).unwrap();
+            // Transactions may be gone already if the block is being rolled back concurrently.
+            let transactions = tx_ids
+                .iter()
+                .map(|tx_id| {
+                    db.get(bincode::serialize(tx_id).unwrap())
+                        .unwrap()
+                        .map(|tx_bytes| bincode::deserialize(&tx_bytes).unwrap())
+                })
+                .collect::<Option<Vec<_>>>()?;
+            let parent_id: BlockId =
+                bincode::deserialize(&db.get(postfixed_key(&block_id, PARENT_POSTFIX)).unwrap()?)
+                    .unwrap();
+            let height: u32 =
+                bincode::deserialize(&db.get(postfixed_key(&block_id, HEIGHT_POSTFIX)).unwrap()?)
+                    .unwrap();
+            let (header, size) =
+                decode_header(db.get(postfixed_key(&block_id, HEADER_POSTFIX)).unwrap());
+            Some(Block {
+                id: block_id,
+                parent_id,
+                height,
+                // Unknown for blocks cached without their header.
+                timestamp: header.as_ref().map_or(0, |h| h.timestamp),
+                transactions,
+                header,
+                size,
+            })
+        })
+        .await
+    }
+
+    async fn get_parent_id(&mut self, block_id: BlockId) -> Option<BlockId> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            db.get(postfixed_key(&block_id, PARENT_POSTFIX))
+                .unwrap()
+                .map(|bytes| bincode::deserialize(&bytes).unwrap())
+        })
+        .await
+    }
+}
+
+/// Header and size of a block as stored under its `:hd` key, if any.
+fn decode_header(bytes: Option<Vec<u8>>) -> (Option<Header>, Option<u32>) {
+    bytes
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .map_or((None, None), |(header, size)| (Some(header), size))
 }
 
 fn postfixed_key(block_id: &BlockId, s: &str) -> Vec<u8> {
@@ -294,6 +462,7 @@ const PARENT_POSTFIX: &str = ":p";
 const CHILD_POSTFIX: &str = ":c";
 const HEIGHT_POSTFIX: &str = ":h";
 const TRANSACTION_POSTFIX: &str = ":t";
+const HEADER_POSTFIX: &str = ":hd";
 
 #[cfg(test)]
 mod tests {
@@ -308,16 +477,22 @@ mod tests {
     use rand::RngCore;
     use sigma_test_util::force_any_val;
 
+    use ergo_lib::chain::transaction::TxIoVec;
+
     use crate::{
         cache::{
             chain_cache::ChainCache,
             rocksdb::{HEIGHT_POSTFIX, TRANSACTION_POSTFIX},
         },
-        client::model::BlockTransaction,
+        client::model::{BlockInput, BlockTransaction},
         model::{Block, BlockRecord},
+        rocksdb::RocksConfig,
     };
 
-    use super::{postfixed_key, ChainCacheRocksDB, OLDEST_BLOCK, PARENT_POSTFIX};
+    use super::{
+        postfixed_key, ChainCacheRocksDB, LegacyBlockTransaction, LAYOUT_VERSION, OLDEST_BLOCK,
+        PARENT_POSTFIX,
+    };
 
     async fn verify_oldest_block(
         expected_block_id: BlockId,
@@ -395,6 +570,8 @@ mod tests {
                 height,
                 timestamp,
                 transactions,
+                header: None,
+                size: None,
             };
             blocks.push(block.clone());
 
@@ -413,4 +590,56 @@ mod tests {
             height += 1;
         }
     }
+
+    #[tokio::test]
+    async fn legacy_transactions_are_migrated_on_open() {
+        let conf = RocksConfig {
+            db_path: format!("./tmp/{}", rand::thread_rng().next_u32()),
+        };
+        let tx = BlockTransaction {
+            id: force_any_val(),
+            inputs: TxIoVec::from_vec(vec![BlockInput {
+                ergo_box: force_any_val(),
+                spending_proof: None,
+            }])
+            .unwrap(),
+            data_inputs: None,
+            outputs: TxIoVec::from_vec(vec![force_any_val()]).unwrap(),
+        };
+        let block = Block {
+            id: BlockId(force_any_val()),
+            parent_id: BlockId(force_any_val()),
+            height: 1,
+            timestamp: 0,
+            transactions: vec![tx.clone()],
+            header: None,
+            size: None,
+        };
+
+        let mut client = ChainCacheRocksDB::new(conf.clone());
+        client.append_block(block.clone()).await;
+        // Rewind the store to how it was written before the layout was versioned.
+        let legacy_tx = LegacyBlockTransaction {
+            id: tx.id,
+            inputs: tx.inputs.mapped_ref(|input| input.ergo_box.clone()),
+            data_inputs: tx.data_inputs.clone(),
+            outputs: tx.outputs.clone(),
+        };
+        client
+            .db
+            .put(
+                bincode::serialize(&tx.id).unwrap(),
+                bincode::serialize(&legacy_tx).unwrap(),
+            )
+            .unwrap();
+        client
+            .db
+            .delete(bincode::serialize(LAYOUT_VERSION).unwrap())
+            .unwrap();
+        drop(client);
+
+        let mut client = ChainCacheRocksDB::new(conf);
+        assert_eq!(client.get_block(block.id).await, Some(block.clone()));
+        assert_eq!(client.take_best_block().await, Some(block));
+    }
 }
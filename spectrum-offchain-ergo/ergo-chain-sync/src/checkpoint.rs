@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use async_std::task::spawn_blocking;
+use async_trait::async_trait;
+
+use crate::model::BlockRecord;
+
+static LAST_PUBLISHED: &str = "LAST_PUBLISHED";
+
+/// Durable marker of the last chain upgrade whose events were acknowledged downstream.
+#[async_trait]
+pub trait Checkpoint: Send {
+    async fn get(&mut self) -> Option<BlockRecord>;
+    async fn set(&mut self, record: BlockRecord);
+}
+
+/// Keeps the checkpoint under {LAST_PUBLISHED} key. Meant to share the database of
+/// [`crate::cache::rocksdb::ChainCacheRocksDB`], so that the checkpoint and the cache it refers
+/// to can't be lost independently.
+pub struct CheckpointRocksDB {
+    pub db: Arc<rocksdb::OptimisticTransactionDB>,
+}
+
+impl CheckpointRocksDB {
+    pub fn new(db: Arc<rocksdb::OptimisticTransactionDB>) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl Checkpoint for CheckpointRocksDB {
+    async fn get(&mut self) -> Option<BlockRecord> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            if let Ok(Some(bytes)) = db.get(bincode::serialize(LAST_PUBLISHED).unwrap()) {
+                bincode::deserialize(&bytes).ok()
+            } else {
+                None
+            }
+        })
+        .await
+    }
+
+    async fn set(&mut self, record: BlockRecord) {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            db.put(
+                bincode::serialize(LAST_PUBLISHED).unwrap(),
+                bincode::serialize(&record).unwrap(),
+            )
+            .unwrap();
+        })
+        .await
+    }
+}
+
+pub struct InMemoryCheckpoint {
+    record: Option<BlockRecord>,
+}
+
+impl InMemoryCheckpoint {
+    pub fn new() -> Self {
+        Self { record: None }
+    }
+}
+
+impl Default for InMemoryCheckpoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Checkpoint for InMemoryCheckpoint {
+    async fn get(&mut self) -> Option<BlockRecord> {
+        self.record.clone()
+    }
+
+    async fn set(&mut self, record: BlockRecord) {
+        self.record = Some(record);
+    }
+}
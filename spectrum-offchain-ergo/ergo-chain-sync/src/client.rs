@@ -1,3 +1,4 @@
 pub mod model;
 pub mod node;
+pub mod pool;
 pub mod types;
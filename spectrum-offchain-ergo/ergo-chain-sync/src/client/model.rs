@@ -3,6 +3,7 @@ use ergo_lib::chain::transaction::{DataInput, Input, Transaction, TxId, TxIoVec}
 use ergo_lib::ergo_chain_types::Header;
 use ergo_lib::ergotree_interpreter::sigma_protocol::prover::{ContextExtension, ProofBytes};
 use ergo_lib::ergotree_ir::chain::ergo_box::{ErgoBox, ErgoBoxCandidate};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -17,7 +18,7 @@ pub struct BlockTransaction {
     /// transaction id
     pub id: TxId,
     /// inputs, that will be spent by this transaction (as ErgoBox instead of Input)
-    pub inputs: TxIoVec<ErgoBox>,
+    pub inputs: TxIoVec<BlockInput>,
     /// inputs, that are not going to be spent by transaction, but will be reachable from inputs
     /// scripts. `dataInputs` scripts will not be executed, thus their scripts costs are not
     /// included in transaction cost and they do not contain spending proofs.
@@ -28,17 +29,18 @@ pub struct BlockTransaction {
 }
 
 impl BlockTransaction {
-    /// Convert BlockTransaction to standard Transaction by creating empty proofs for inputs
+    /// Convert BlockTransaction to standard Transaction. Inputs the node returned no spending
+    /// proof for are given empty proofs.
     pub fn to_transaction(
         self,
     ) -> Result<Transaction, ergo_lib::ergotree_ir::serialization::SigmaSerializationError> {
-        let inputs = self.inputs.mapped_ref(|ergo_box| {
+        let inputs = self.inputs.mapped_ref(|input| {
             Input::new(
-                ergo_box.box_id(),
-                ProverResult {
+                input.ergo_box.box_id(),
+                input.spending_proof.clone().unwrap_or(ProverResult {
                     proof: ProofBytes::Empty,
                     extension: ContextExtension::empty(),
-                },
+                }),
             )
         });
 
@@ -55,9 +57,132 @@ impl BlockTransaction {
     }
 }
 
+/// Box spent by a transaction, along with the proof and context extension it was spent with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockInput {
+    pub ergo_box: ErgoBox,
+    /// Not every endpoint returns proofs of inputs.
+    pub spending_proof: Option<ProverResult>,
+}
+
+/// The node represents an input as the fields of the box merged with `spendingProof`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockInputJsonRef<'a> {
+    #[serde(flatten)]
+    ergo_box: &'a ErgoBox,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spending_proof: &'a Option<ProverResult>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockInputJson {
+    #[serde(flatten)]
+    ergo_box: ErgoBox,
+    #[serde(default)]
+    spending_proof: Option<ProverResult>,
+}
+
+/// Binary formats (e.g. bincode, used by the chain cache) don't support flattening, so
+/// inputs are represented there as plain pairs.
+impl Serialize for BlockInput {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            BlockInputJsonRef {
+                ergo_box: &self.ergo_box,
+                spending_proof: &self.spending_proof,
+            }
+            .serialize(serializer)
+        } else {
+            (&self.ergo_box, &self.spending_proof).serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockInput {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let BlockInputJson {
+                ergo_box,
+                spending_proof,
+            } = BlockInputJson::deserialize(deserializer)?;
+            Ok(Self {
+                ergo_box,
+                spending_proof,
+            })
+        } else {
+            let (ergo_box, spending_proof) = Deserialize::deserialize(deserializer)?;
+            Ok(Self {
+                ergo_box,
+                spending_proof,
+            })
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FullBlock {
     pub header: Header,
     pub transactions: Vec<BlockTransaction>,
+    #[serde(default)]
+    pub size: Option<u32>,
+}
+
+/// Extension section of a block.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockExtension {
+    /// Hex-encoded root of the Merkle tree of the fields.
+    pub digest: String,
+    /// Hex-encoded keys and values.
+    pub fields: Vec<(String, String)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::chain::transaction::prover_result::ProverResult;
+    use ergo_lib::ergotree_interpreter::sigma_protocol::prover::{ContextExtension, ProofBytes};
+    use sigma_test_util::force_any_val;
+
+    use super::BlockInput;
+
+    fn inputs() -> Vec<BlockInput> {
+        vec![
+            BlockInput {
+                ergo_box: force_any_val(),
+                spending_proof: Some(ProverResult {
+                    proof: ProofBytes::Some(vec![7; 56]),
+                    extension: ContextExtension::empty(),
+                }),
+            },
+            BlockInput {
+                ergo_box: force_any_val(),
+                spending_proof: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn block_inputs_round_trip_through_json() {
+        for input in inputs() {
+            let json = serde_json::to_value(&input).unwrap();
+            // Fields of the box sit next to the proof, as the node returns them.
+            assert!(json.get("boxId").is_some());
+            assert_eq!(
+                json.get("spendingProof").is_some(),
+                input.spending_proof.is_some()
+            );
+            assert_eq!(serde_json::from_value::<BlockInput>(json).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn block_inputs_round_trip_through_bincode() {
+        for input in inputs() {
+            let bytes = bincode::serialize(&input).unwrap();
+            assert_eq!(bincode::deserialize::<BlockInput>(&bytes).unwrap(), input);
+        }
+    }
 }
@@ -1,12 +1,15 @@
 use async_trait::async_trait;
 use derive_more::From;
 use ergo_lib::ergo_chain_types::{BlockId, Header};
+use futures::{stream, StreamExt, TryStreamExt};
+use isahc::http::StatusCode;
 use isahc::{AsyncReadResponseExt, HttpClient};
-use log::{error, info};
+use log::{error, info, warn};
 use thiserror::Error;
 
-use crate::client::model::{ApiInfo, BlockTransaction, FullBlock};
+use crate::client::model::{ApiInfo, BlockExtension, BlockTransaction, FullBlock};
 use crate::client::types::Url;
+use crate::model::BlockRecord;
 
 use super::types::with_path;
 
@@ -22,10 +25,26 @@ pub enum Error {
     Io(std::io::Error),
     #[error("unsuccessful request: {0}")]
     UnsuccessfulRequest(String),
+    /// The node refused the request for its size, or was temporarily unavailable.
+    #[error("node overloaded: {0}")]
+    Overloaded(String),
     #[error("No block found")]
     NoBlock,
 }
 
+impl Error {
+    /// Whether the request likely failed for being too heavy, i.e. it timed out or the node
+    /// refused it, e.g. for exceeding its payload limit.
+    pub fn is_overload(&self) -> bool {
+        match self {
+            Error::Overloaded(_) => true,
+            Error::Isahc(e) => e.is_timeout(),
+            Error::Io(e) => e.kind() == std::io::ErrorKind::TimedOut,
+            _ => false,
+        }
+    }
+}
+
 #[async_trait]
 pub trait ErgoNetwork: Send + Sync {
     async fn get_blocks_range(
@@ -33,6 +52,13 @@ pub trait ErgoNetwork: Send + Sync {
         from_height: u32,
         to_height: u32,
     ) -> Result<Vec<BlockId>, Error>;
+    /// IDs and heights of the headers of the node's best chain above `from_height`, up to
+    /// `to_height`.
+    async fn get_chain_slice(
+        &self,
+        from_height: u32,
+        to_height: u32,
+    ) -> Result<Vec<BlockRecord>, Error>;
     async fn get_best_height(&self) -> Result<u32, Error>;
     async fn fetch_mempool(
         &self,
@@ -40,11 +66,15 @@ pub trait ErgoNetwork: Send + Sync {
         limit: usize,
     ) -> Result<Vec<BlockTransaction>, Error>;
 
+    /// Full blocks from `from_height` on, fetched in chunks of `chunk_size`, with up to
+    /// `prefetch` chunk requests in flight. The batch is cut short at the first block which
+    /// doesn't link to the previous one, as the node may switch chains between requests.
     async fn get_blocks_batch(
         &self,
         from_height: u32,
         batch_size: u32,
         chunk_size: usize,
+        prefetch: usize,
     ) -> Result<Vec<FullBlock>, Error> {
         let batch_end = from_height + batch_size;
         info!(target: "chain_sync", "Fetching blocks range from {} to {}", from_height, batch_end);
@@ -53,26 +83,42 @@ pub trait ErgoNetwork: Send + Sync {
         if block_ids.is_empty() {
             return Err(Error::NoBlock);
         }
-        self.get_full_blocks_in_chunks(block_ids, chunk_size).await
+        let mut full_blocks = self
+            .get_full_blocks_in_chunks(block_ids, chunk_size, prefetch)
+            .await?;
+        if let Some(pos) = full_blocks
+            .windows(2)
+            .position(|pair| pair[1].header.parent_id != pair[0].header.id)
+        {
+            warn!(
+                target: "chain_sync",
+                "Block at height {} doesn't link to the previous one, cutting batch short",
+                full_blocks[pos + 1].header.height
+            );
+            full_blocks.truncate(pos + 1);
+        }
+        Ok(full_blocks)
     }
 
+    /// Chunks are requested concurrently, up to `prefetch` at a time, and reassembled in order.
     async fn get_full_blocks_in_chunks(
         &self,
         block_ids: Vec<BlockId>,
         chunk_size: usize,
+        prefetch: usize,
     ) -> Result<Vec<FullBlock>, Error> {
-        let mut full_blocks = Vec::with_capacity(block_ids.len());
-
         // Process in smaller chunks to avoid large payload errors
-        for chunk in block_ids.chunks(chunk_size) {
-            let chunk_result = self.get_full_blocks(chunk.to_vec()).await?;
-            full_blocks.extend(chunk_result);
-        }
-
-        Ok(full_blocks)
+        let chunks: Vec<Vec<FullBlock>> = stream::iter(block_ids.chunks(chunk_size))
+            .map(|chunk| self.get_full_blocks(chunk.to_vec()))
+            .buffered(prefetch.max(1))
+            .try_collect()
+            .await?;
+        Ok(chunks.into_iter().flatten().collect())
     }
 
     async fn get_full_blocks(&self, block_ids: Vec<BlockId>) -> Result<Vec<FullBlock>, Error>;
+
+    async fn get_block_extension(&self, block_id: BlockId) -> Result<BlockExtension, Error>;
 }
 
 #[derive(Clone)]
@@ -95,14 +141,22 @@ impl ErgoNetwork for ErgoNodeHttpClient {
         to_height: u32,
     ) -> Result<Vec<BlockId>, Error> {
         info!(target: "ergo_network", "Fetching blocks range from {} to {}", from_height, to_height);
+        let headers = self.get_chain_slice(from_height - 1, to_height + 1).await?;
+        Ok(headers.into_iter().map(|h| h.id).collect())
+    }
+
+    async fn get_chain_slice(
+        &self,
+        from_height: u32,
+        to_height: u32,
+    ) -> Result<Vec<BlockRecord>, Error> {
         let mut resp = self
             .client
             .get_async(with_path(
                 &self.base_url,
                 &format!(
                     "/blocks/chainSlice?fromHeight={}&toHeight={}",
-                    from_height - 1,
-                    to_height + 1
+                    from_height, to_height
                 ),
             ))
             .await?;
@@ -110,7 +164,13 @@ impl ErgoNetwork for ErgoNodeHttpClient {
         if resp.status().is_success() {
             let body = resp.text().await?;
             let headers: Vec<Header> = serde_json::from_str(&body)?;
-            Ok(headers.into_iter().map(|h| h.id).collect())
+            Ok(headers
+                .into_iter()
+                .map(|h| BlockRecord {
+                    id: h.id,
+                    height: h.height,
+                })
+                .collect())
         } else {
             Err(Error::UnsuccessfulRequest(format!(
                 "expected 200 from /blocks/chainSlice, got {}",
@@ -145,12 +205,36 @@ impl ErgoNetwork for ErgoNodeHttpClient {
         if resp.status().is_success() {
             resp.json().await.map_err(Error::from)
         } else {
+            let status = resp.status();
             let error_body = resp.text().await?;
             error!("Unexpected response from node: {}", error_body);
-            Err(Error::UnsuccessfulRequest(format!(
+            let details = format!(
                 "expected 200 from /blockchain/blocks/byHeaderIds, got {} with body: {}",
-                resp.status(),
-                error_body
+                status, error_body
+            );
+            if is_overload_response(status, &error_body) {
+                Err(Error::Overloaded(details))
+            } else {
+                Err(Error::UnsuccessfulRequest(details))
+            }
+        }
+    }
+
+    async fn get_block_extension(&self, block_id: BlockId) -> Result<BlockExtension, Error> {
+        let mut resp = self
+            .client
+            .get_async(with_path(
+                &self.base_url,
+                &format!("/blocks/{}/extension", block_id),
+            ))
+            .await?;
+        if resp.status().is_success() {
+            resp.json().await.map_err(Error::from)
+        } else {
+            Err(Error::UnsuccessfulRequest(format!(
+                "expected 200 from /blocks/{}/extension, got {}",
+                block_id,
+                resp.status()
             )))
         }
     }
@@ -200,3 +284,155 @@ impl ErgoNetwork for ErgoNodeHttpClient {
         }
     }
 }
+
+/// Whether the response means the request was too heavy for the node. Besides 413 and 503
+/// responses, entities exceeding the node's size limit may be reported under other statuses,
+/// naming the exception in the body.
+fn is_overload_response(status: StatusCode, body: &str) -> bool {
+    status == StatusCode::PAYLOAD_TOO_LARGE
+        || status == StatusCode::SERVICE_UNAVAILABLE
+        || body.contains("EntityStreamSizeException")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use ergo_lib::ergo_chain_types::{BlockId, Digest32, Header};
+    use futures_timer::Delay;
+    use isahc::http::StatusCode;
+    use sigma_test_util::force_any_val;
+
+    use crate::client::model::{BlockExtension, BlockTransaction, FullBlock};
+    use crate::client::node::{is_overload_response, ErgoNetwork, Error};
+    use crate::model::BlockRecord;
+
+    /// Node answering requests for lower blocks more slowly, so that chunks requested
+    /// concurrently complete in reverse order.
+    struct SlowNetwork {
+        chain: Vec<FullBlock>,
+        /// Height of the first block of every chunk, in the order the chunks completed.
+        completed: Mutex<Vec<u32>>,
+    }
+
+    #[async_trait]
+    impl ErgoNetwork for SlowNetwork {
+        async fn get_blocks_range(
+            &self,
+            from_height: u32,
+            to_height: u32,
+        ) -> Result<Vec<BlockId>, Error> {
+            Ok(self
+                .chain
+                .iter()
+                .filter(|blk| blk.header.height >= from_height && blk.header.height <= to_height)
+                .map(|blk| blk.header.id)
+                .collect())
+        }
+
+        async fn get_chain_slice(
+            &self,
+            _from_height: u32,
+            _to_height: u32,
+        ) -> Result<Vec<BlockRecord>, Error> {
+            Err(Error::NoBlock)
+        }
+
+        async fn get_best_height(&self) -> Result<u32, Error> {
+            Ok(self.chain.last().map_or(0, |blk| blk.header.height))
+        }
+
+        async fn fetch_mempool(
+            &self,
+            _offset: usize,
+            _limit: usize,
+        ) -> Result<Vec<BlockTransaction>, Error> {
+            Ok(vec![])
+        }
+
+        async fn get_full_blocks(&self, block_ids: Vec<BlockId>) -> Result<Vec<FullBlock>, Error> {
+            let blocks: Vec<FullBlock> = block_ids
+                .iter()
+                .filter_map(|id| self.chain.iter().find(|blk| blk.header.id == *id).cloned())
+                .collect();
+            let first_height = blocks[0].header.height;
+            let best_height = self.get_best_height().await?;
+            Delay::new(Duration::from_millis(
+                20 * (best_height - first_height) as u64,
+            ))
+            .await;
+            self.completed.lock().unwrap().push(first_height);
+            Ok(blocks)
+        }
+
+        async fn get_block_extension(&self, _block_id: BlockId) -> Result<BlockExtension, Error> {
+            Err(Error::NoBlock)
+        }
+    }
+
+    fn block_id(seed: u8) -> BlockId {
+        BlockId(Digest32::from([seed; 32]))
+    }
+
+    /// Blocks at heights `1..=to_height`, each linking to the previous one.
+    fn chain(to_height: u32) -> Vec<FullBlock> {
+        (1..=to_height)
+            .map(|height| FullBlock {
+                header: Header {
+                    id: block_id(height as u8),
+                    parent_id: block_id(height as u8 - 1),
+                    height,
+                    ..force_any_val::<Header>()
+                },
+                transactions: vec![],
+                size: None,
+            })
+            .collect()
+    }
+
+    fn heights(blocks: &[FullBlock]) -> Vec<u32> {
+        blocks.iter().map(|blk| blk.header.height).collect()
+    }
+
+    #[tokio::test]
+    async fn chunks_completing_out_of_order_are_reassembled_in_order() {
+        let network = SlowNetwork {
+            chain: chain(6),
+            completed: Mutex::new(vec![]),
+        };
+        let blocks = network.get_blocks_batch(1, 5, 2, 3).await.unwrap();
+        assert_eq!(heights(&blocks), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(*network.completed.lock().unwrap(), vec![5, 3, 1]);
+    }
+
+    #[tokio::test]
+    async fn batch_is_cut_short_at_the_first_block_not_linking() {
+        let mut chain = chain(6);
+        // The node switched chains between chunk requests.
+        chain[3].header.parent_id = block_id(100);
+        let network = SlowNetwork {
+            chain,
+            completed: Mutex::new(vec![]),
+        };
+        let blocks = network.get_blocks_batch(1, 5, 2, 3).await.unwrap();
+        assert_eq!(heights(&blocks), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn overload_is_told_from_status_or_body() {
+        assert!(is_overload_response(StatusCode::PAYLOAD_TOO_LARGE, ""));
+        assert!(is_overload_response(StatusCode::SERVICE_UNAVAILABLE, ""));
+        assert!(is_overload_response(
+            StatusCode::BAD_REQUEST,
+            "EntityStreamSizeException: incoming entity size exceeded size limit"
+        ));
+        assert!(!is_overload_response(
+            StatusCode::BAD_REQUEST,
+            "malformed id"
+        ));
+        assert!(!is_overload_response(StatusCode::NOT_FOUND, ""));
+        assert!(!is_overload_response(StatusCode::INTERNAL_SERVER_ERROR, ""));
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use ergo_lib::ergo_chain_types::BlockId;
+use futures::future::{join_all, BoxFuture};
+use log::{info, warn};
+
+use crate::client::model::{BlockExtension, BlockTransaction, FullBlock};
+use crate::client::node::{ErgoNetwork, Error};
+use crate::model::BlockRecord;
+
+/// Weight of the outcome of the latest request in the error rate of a node.
+const ERROR_RATE_WEIGHT: f64 = 0.2;
+/// Nodes failing more often than this are avoided.
+const MAX_ERROR_RATE: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy)]
+pub struct NodePoolConf {
+    /// Nodes more than this many blocks behind the highest one are considered lagging.
+    pub max_lag: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+struct NodeHealth {
+    best_height: Option<u32>,
+    /// Moving average of the share of failed requests.
+    error_rate: f64,
+    /// Whether the node disagreed with the majority of nodes on the chain at the last check.
+    diverged: bool,
+}
+
+struct PooledNode<C> {
+    client: C,
+    health: Mutex<NodeHealth>,
+}
+
+/// Client of several nodes. Requests are routed to the healthiest node at the chain tip, and
+/// retried on the other nodes if it fails. Heights of all nodes are refreshed whenever the best
+/// height is requested, and once per block, nodes disagreeing with the majority on the ID of a
+/// recent block are detected as diverging and avoided.
+pub struct ErgoNodePool<C> {
+    nodes: Vec<PooledNode<C>>,
+    conf: NodePoolConf,
+    /// Height the chains of nodes were last compared at.
+    last_checked_height: Mutex<Option<u32>>,
+}
+
+impl<C: ErgoNetwork> ErgoNodePool<C> {
+    pub fn new(clients: Vec<C>, conf: NodePoolConf) -> Self {
+        assert!(!clients.is_empty(), "Node pool requires at least one node");
+        Self {
+            nodes: clients
+                .into_iter()
+                .map(|client| PooledNode {
+                    client,
+                    health: Mutex::new(NodeHealth::default()),
+                })
+                .collect(),
+            conf,
+            last_checked_height: Mutex::new(None),
+        }
+    }
+
+    fn health(&self, node: usize) -> NodeHealth {
+        self.nodes[node].health.lock().unwrap().clone()
+    }
+
+    fn record_outcome(&self, node: usize, failed: bool) {
+        let mut health = self.nodes[node].health.lock().unwrap();
+        let outcome = if failed { 1.0 } else { 0.0 };
+        health.error_rate =
+            health.error_rate * (1.0 - ERROR_RATE_WEIGHT) + outcome * ERROR_RATE_WEIGHT;
+    }
+
+    /// Highest height reported by a node which doesn't diverge.
+    fn best_height(&self) -> Option<u32> {
+        (0..self.nodes.len())
+            .map(|node| self.health(node))
+            .filter(|health| !health.diverged)
+            .filter_map(|health| health.best_height)
+            .max()
+    }
+
+    /// Indices of nodes in the order requests are tried: healthy nodes at the tip by error rate,
+    /// then all others, diverging ones last.
+    fn route(&self) -> Vec<usize> {
+        let best_height = self.best_height();
+        let at_tip = |health: &NodeHealth| match (health.best_height, best_height) {
+            (Some(height), Some(best_height)) => height + self.conf.max_lag >= best_height,
+            _ => false,
+        };
+        let mut nodes: Vec<(usize, NodeHealth)> = (0..self.nodes.len())
+            .map(|node| (node, self.health(node)))
+            .collect();
+        nodes.sort_by(|(_, a), (_, b)| {
+            let healthy =
+                |h: &NodeHealth| !h.diverged && h.error_rate < MAX_ERROR_RATE && at_tip(h);
+            healthy(b)
+                .cmp(&healthy(a))
+                .then(a.diverged.cmp(&b.diverged))
+                .then(a.error_rate.total_cmp(&b.error_rate))
+        });
+        nodes.into_iter().map(|(node, _)| node).collect()
+    }
+
+    /// Runs the given request against nodes in the order of [`Self::route`] until one succeeds.
+    async fn with_failover<T, F>(&self, request: &str, f: F) -> Result<T, Error>
+    where
+        F: for<'c> Fn(&'c C) -> BoxFuture<'c, Result<T, Error>> + Send + Sync,
+        T: Send,
+    {
+        let mut last_error = None;
+        for node in self.route() {
+            match f(&self.nodes[node].client).await {
+                Ok(res) => {
+                    self.record_outcome(node, false);
+                    return Ok(res);
+                }
+                Err(e) => {
+                    self.record_outcome(node, true);
+                    warn!(target: "ergo_network", "Node #{} failed to serve {}: {}", node, request, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap())
+    }
+
+    /// Compares the IDs of the block at the highest height reached by a majority of nodes,
+    /// marking nodes disagreeing with the majority as diverging. Nodes behind that height keep
+    /// their state, so that lagging nodes don't pin the check below a recent fork, while nodes
+    /// running ahead on a chain of their own are still compared.
+    async fn check_divergence(&self) {
+        let mut heights: Vec<(usize, u32)> = (0..self.nodes.len())
+            .filter_map(|node| self.health(node).best_height.map(|height| (node, height)))
+            .collect();
+        if heights.len() < 2 {
+            return;
+        }
+        heights.sort_by(|(_, a), (_, b)| b.cmp(a));
+        let height = heights[heights.len() / 2].1;
+        let candidates: Vec<(usize, u32)> =
+            heights.into_iter().filter(|(_, h)| *h >= height).collect();
+        {
+            let mut last_checked_height = self.last_checked_height.lock().unwrap();
+            if *last_checked_height == Some(height) {
+                return;
+            }
+            *last_checked_height = Some(height);
+        }
+        let ids: Vec<(usize, Option<BlockId>)> =
+            join_all(candidates.iter().map(|(node, _)| async move {
+                let slice = self.nodes[*node]
+                    .client
+                    .get_chain_slice(height.saturating_sub(1), height + 1)
+                    .await;
+                self.record_outcome(*node, slice.is_err());
+                let id = slice
+                    .ok()
+                    .and_then(|slice| slice.into_iter().find(|blk| blk.height == height))
+                    .map(|blk| blk.id);
+                (*node, id)
+            }))
+            .await;
+        // Ties are broken in favour of the nodes listed first.
+        let mut votes: HashMap<BlockId, (usize, usize)> = HashMap::new();
+        for (node, id) in &ids {
+            if let Some(id) = id {
+                let (count, first_node) = votes.entry(*id).or_insert((0, *node));
+                *count += 1;
+                *first_node = (*first_node).min(*node);
+            }
+        }
+        let majority = votes
+            .into_iter()
+            .max_by(|(_, (count_a, node_a)), (_, (count_b, node_b))| {
+                count_a.cmp(count_b).then(node_b.cmp(node_a))
+            })
+            .map(|(id, _)| id);
+        for (node, id) in ids {
+            // Nodes which failed to answer keep their state.
+            if let Some(id) = id {
+                let diverged = Some(id) != majority;
+                let mut health = self.nodes[node].health.lock().unwrap();
+                if health.diverged != diverged {
+                    if diverged {
+                        warn!(target: "ergo_network", "Node #{} diverges from the majority at height {}", node, height);
+                    } else {
+                        info!(target: "ergo_network", "Node #{} agrees with the majority again at height {}", node, height);
+                    }
+                    health.diverged = diverged;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<C: ErgoNetwork> ErgoNetwork for ErgoNodePool<C> {
+    async fn get_blocks_range(
+        &self,
+        from_height: u32,
+        to_height: u32,
+    ) -> Result<Vec<BlockId>, Error> {
+        self.with_failover("blocks range", |node| {
+            node.get_blocks_range(from_height, to_height)
+        })
+        .await
+    }
+
+    async fn get_chain_slice(
+        &self,
+        from_height: u32,
+        to_height: u32,
+    ) -> Result<Vec<BlockRecord>, Error> {
+        self.with_failover("chain slice", |node| {
+            node.get_chain_slice(from_height, to_height)
+        })
+        .await
+    }
+
+    /// Refreshes the heights of all nodes, returning the highest one of a node which doesn't
+    /// diverge.
+    async fn get_best_height(&self) -> Result<u32, Error> {
+        let heights = join_all(self.nodes.iter().map(|node| node.client.get_best_height())).await;
+        let mut last_error = None;
+        for (node, height) in heights.into_iter().enumerate() {
+            self.record_outcome(node, height.is_err());
+            match height {
+                Ok(height) => self.nodes[node].health.lock().unwrap().best_height = Some(height),
+                Err(e) => {
+                    warn!(target: "ergo_network", "Node #{} failed to report its height: {}", node, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        self.check_divergence().await;
+        match (self.best_height(), last_error) {
+            (Some(height), _) => Ok(height),
+            (None, Some(e)) => Err(e),
+            (None, None) => Err(Error::UnsuccessfulRequest(
+                "all nodes diverge from each other".to_string(),
+            )),
+        }
+    }
+
+    async fn fetch_mempool(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<BlockTransaction>, Error> {
+        self.with_failover("mempool", |node| node.fetch_mempool(offset, limit))
+            .await
+    }
+
+    async fn get_full_blocks(&self, block_ids: Vec<BlockId>) -> Result<Vec<FullBlock>, Error> {
+        self.with_failover("full blocks", |node| {
+            node.get_full_blocks(block_ids.clone())
+        })
+        .await
+    }
+
+    async fn get_block_extension(&self, block_id: BlockId) -> Result<BlockExtension, Error> {
+        self.with_failover("block extension", |node| node.get_block_extension(block_id))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+    use ergo_lib::ergo_chain_types::{BlockId, Digest32};
+
+    use crate::client::model::{BlockExtension, BlockTransaction, FullBlock};
+    use crate::client::node::{ErgoNetwork, Error};
+    use crate::client::pool::{ErgoNodePool, NodePoolConf};
+    use crate::model::BlockRecord;
+
+    /// Fork the chains of nodes share below their fork heights.
+    const MAIN_CHAIN: u8 = 1;
+
+    /// Node at a fixed height whose blocks from `fork_height` on have IDs derived from `fork`,
+    /// and the ones below it IDs of the [`MAIN_CHAIN`].
+    struct FakeNode {
+        height: u32,
+        fork: u8,
+        fork_height: u32,
+        failing: AtomicBool,
+        served: AtomicUsize,
+    }
+
+    impl FakeNode {
+        fn new(height: u32, fork: u8) -> Self {
+            Self::forked_at(height, fork, 0)
+        }
+
+        fn forked_at(height: u32, fork: u8, fork_height: u32) -> Self {
+            Self {
+                height,
+                fork,
+                fork_height,
+                failing: AtomicBool::new(false),
+                served: AtomicUsize::new(0),
+            }
+        }
+
+        fn block_id(&self, height: u32) -> BlockId {
+            let fork = if height >= self.fork_height {
+                self.fork
+            } else {
+                MAIN_CHAIN
+            };
+            BlockId(Digest32::from([fork; 32]))
+        }
+
+        fn serve(&self) -> Result<(), Error> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(Error::UnsuccessfulRequest("node is down".to_string()));
+            }
+            self.served.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl ErgoNetwork for FakeNode {
+        async fn get_blocks_range(
+            &self,
+            from_height: u32,
+            to_height: u32,
+        ) -> Result<Vec<BlockId>, Error> {
+            self.serve()?;
+            Ok((from_height..=to_height.min(self.height))
+                .map(|height| self.block_id(height))
+                .collect())
+        }
+
+        async fn get_chain_slice(
+            &self,
+            from_height: u32,
+            to_height: u32,
+        ) -> Result<Vec<BlockRecord>, Error> {
+            self.serve()?;
+            Ok((from_height..=to_height.min(self.height))
+                .map(|height| BlockRecord {
+                    id: self.block_id(height),
+                    height,
+                })
+                .collect())
+        }
+
+        async fn get_best_height(&self) -> Result<u32, Error> {
+            self.serve()?;
+            Ok(self.height)
+        }
+
+        async fn fetch_mempool(
+            &self,
+            _offset: usize,
+            _limit: usize,
+        ) -> Result<Vec<BlockTransaction>, Error> {
+            self.serve()?;
+            Ok(vec![])
+        }
+
+        async fn get_full_blocks(&self, _block_ids: Vec<BlockId>) -> Result<Vec<FullBlock>, Error> {
+            self.serve()?;
+            Ok(vec![])
+        }
+
+        async fn get_block_extension(&self, _block_id: BlockId) -> Result<BlockExtension, Error> {
+            Err(Error::NoBlock)
+        }
+    }
+
+    const CONF: NodePoolConf = NodePoolConf { max_lag: 2 };
+
+    fn served(pool: &ErgoNodePool<FakeNode>, node: usize) -> usize {
+        pool.nodes[node].client.served.load(Ordering::SeqCst)
+    }
+
+    fn set_failing(pool: &ErgoNodePool<FakeNode>, node: usize, failing: bool) {
+        pool.nodes[node]
+            .client
+            .failing
+            .store(failing, Ordering::SeqCst);
+    }
+
+    #[tokio::test]
+    async fn requests_fail_over_to_other_nodes() {
+        let pool = ErgoNodePool::new(
+            vec![FakeNode::new(10, MAIN_CHAIN), FakeNode::new(10, MAIN_CHAIN)],
+            CONF,
+        );
+        assert_eq!(pool.get_best_height().await.unwrap(), 10);
+
+        set_failing(&pool, 0, true);
+        assert!(pool.fetch_mempool(0, 10).await.is_ok());
+        assert_eq!(served(&pool, 1), 3);
+
+        // The failed node is tried last while the other one keeps serving requests.
+        set_failing(&pool, 0, false);
+        let served_by_first = served(&pool, 0);
+        assert!(pool.fetch_mempool(0, 10).await.is_ok());
+        assert_eq!(served(&pool, 0), served_by_first);
+
+        set_failing(&pool, 1, true);
+        assert!(pool.fetch_mempool(0, 10).await.is_ok());
+        assert_eq!(served(&pool, 0), served_by_first + 1);
+
+        set_failing(&pool, 0, true);
+        assert!(pool.fetch_mempool(0, 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn lagging_nodes_are_avoided() {
+        let pool = ErgoNodePool::new(
+            vec![FakeNode::new(10, MAIN_CHAIN), FakeNode::new(20, MAIN_CHAIN)],
+            CONF,
+        );
+        assert_eq!(pool.get_best_height().await.unwrap(), 20);
+
+        let served_by_first = served(&pool, 0);
+        assert!(pool.get_full_blocks(vec![]).await.is_ok());
+        assert_eq!(served(&pool, 0), served_by_first);
+    }
+
+    #[tokio::test]
+    async fn diverging_nodes_are_detected_and_avoided() {
+        let nodes = vec![
+            FakeNode::new(30, 66),
+            FakeNode::new(10, MAIN_CHAIN),
+            FakeNode::new(10, MAIN_CHAIN),
+        ];
+        let pool = ErgoNodePool::new(nodes, CONF);
+        // The diverging node is ahead of the others, which would make them look lagging.
+        assert_eq!(pool.get_best_height().await.unwrap(), 10);
+
+        let served_by_first = served(&pool, 0);
+        assert!(pool.get_blocks_range(5, 10).await.is_ok());
+        assert_eq!(served(&pool, 0), served_by_first);
+    }
+
+    #[tokio::test]
+    async fn nodes_diverging_at_the_tip_are_detected_despite_lagging_nodes() {
+        let nodes = vec![
+            FakeNode::forked_at(20, 66, 16),
+            FakeNode::new(20, MAIN_CHAIN),
+            FakeNode::new(20, MAIN_CHAIN),
+            FakeNode::new(5, MAIN_CHAIN),
+        ];
+        let pool = ErgoNodePool::new(nodes, CONF);
+        // All nodes agree at the height of the lagging one, chains are compared at the tip.
+        assert_eq!(pool.get_best_height().await.unwrap(), 20);
+        assert!(pool.health(0).diverged);
+        assert!(!pool.health(3).diverged);
+        assert_eq!(*pool.last_checked_height.lock().unwrap(), Some(20));
+
+        let served_by_first = served(&pool, 0);
+        assert!(pool.get_blocks_range(10, 20).await.is_ok());
+        assert_eq!(served(&pool, 0), served_by_first);
+    }
+}
@@ -1,23 +1,31 @@
 use std::cmp::max;
+use std::collections::HashMap;
+use std::mem;
 use std::sync::{Arc, Once};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_stream::stream;
+use ergo_lib::ergo_chain_types::BlockId;
 use futures::lock::Mutex;
-use futures::Stream;
+use futures::{Stream, StreamExt};
 use futures_timer::Delay;
 use log::{error, trace};
 use pin_project::pin_project;
 
 use crate::cache::chain_cache::ChainCache;
+use crate::client::model::FullBlock;
 use crate::client::node::{ErgoNetwork, Error};
-use crate::model::Block;
+use crate::constants::ERGO_MAX_ROLLBACK_DEPTH;
+use crate::model::{Block, BlockRecord};
+use crate::sizing::{AdaptiveSizingConf, BatchSizing};
 
 pub mod cache;
+pub mod checkpoint;
 pub mod client;
 pub mod constants;
 pub mod model;
 pub mod rocksdb;
+pub mod sizing;
 
 #[derive(Debug, Clone)]
 pub enum ChainUpgrade {
@@ -25,6 +33,31 @@ pub enum ChainUpgrade {
     RollBackward(Block),
 }
 
+impl ChainUpgrade {
+    /// Chain tip as seen downstream once this upgrade is applied.
+    pub fn resulting_tip(&self) -> BlockRecord {
+        match self {
+            ChainUpgrade::RollForward(blk) => BlockRecord {
+                id: blk.id,
+                height: blk.height,
+            },
+            ChainUpgrade::RollBackward(blk) => BlockRecord {
+                id: blk.parent_id,
+                height: blk.height.saturating_sub(1),
+            },
+        }
+    }
+}
+
+/// Reasons local chain state can't be rewound to a checkpoint.
+#[derive(thiserror::Error, Debug)]
+pub enum ResumeError {
+    #[error("Checkpoint is more than {0} blocks away from local chain")]
+    TooFarFromLocalChain(u32),
+    #[error("Unacknowledged block {0} is unknown to the node")]
+    UnknownBlock(BlockId),
+}
+
 #[derive(Debug, Clone)]
 struct SyncState {
     next_height: u32,
@@ -40,6 +73,19 @@ impl SyncState {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct ChainSyncConf {
+    /// Number of blocks to request at once, initially if sizing is adaptive.
+    pub batch_size: u32,
+    /// Number of full blocks per request of a batch, initially if sizing is adaptive.
+    pub chunk_size: usize,
+    /// Bounds of batch and chunk sizes, which are fixed if not given.
+    pub adaptive_sizing: Option<AdaptiveSizingConf>,
+    /// Max number of chunk requests in flight while far from the chain tip.
+    pub prefetch: usize,
+    pub throttle_ms: u64,
+}
+
 #[async_trait::async_trait(?Send)]
 pub trait InitChainSync<TChainSync> {
     async fn init(
@@ -52,25 +98,15 @@ pub trait InitChainSync<TChainSync> {
 pub struct ChainSyncNonInit<'a, TClient, TCache> {
     client: &'a TClient,
     cache: TCache,
-    batch_size: u32,
-    chunk_size: usize,
-    throttle_ms: u64,
+    conf: ChainSyncConf,
 }
 
 impl<'a, TClient, TCache> ChainSyncNonInit<'a, TClient, TCache> {
-    pub fn new(
-        client: &'a TClient,
-        cache: TCache,
-        batch_size: u32,
-        chunk_size: usize,
-        throttle_ms: u64,
-    ) -> Self {
+    pub fn new(client: &'a TClient, cache: TCache, conf: ChainSyncConf) -> Self {
         Self {
             client,
             cache,
-            batch_size,
-            chunk_size,
-            throttle_ms,
+            conf,
         }
     }
 }
@@ -92,9 +128,7 @@ where
             self.client,
             self.cache,
             tip_reached_signal,
-            self.batch_size,
-            self.chunk_size,
-            self.throttle_ms,
+            self.conf,
         )
         .await
     }
@@ -109,9 +143,10 @@ pub struct ChainSync<'a, TClient, TCache> {
     #[pin]
     delay: Mutex<Option<Delay>>,
     tip_reached_signal: Option<&'a Once>,
-    batch_size: u32,
-    chunk_size: usize,
-    throttle_ms: u64,
+    conf: ChainSyncConf,
+    sizing: Mutex<BatchSizing>,
+    /// Upgrades to be emitted before anything is requested from the network.
+    pending: Mutex<Vec<ChainUpgrade>>,
 }
 
 impl<'a, TClient, TCache> ChainSync<'a, TClient, TCache>
@@ -124,9 +159,7 @@ where
         client: &'a TClient,
         mut cache: TCache,
         tip_reached_signal: Option<&'a Once>,
-        batch_size: u32,
-        chunk_size: usize,
-        throttle_ms: u64,
+        conf: ChainSyncConf,
     ) -> ChainSync<'a, TClient, TCache> {
         let best_block = cache.get_best_block().await;
         let start_at = if let Some(best_block) = best_block {
@@ -144,16 +177,78 @@ where
             })),
             delay: Mutex::new(None),
             tip_reached_signal,
-            batch_size,
-            chunk_size,
-            throttle_ms,
+            conf,
+            sizing: Mutex::new(BatchSizing::new(
+                conf.batch_size,
+                conf.chunk_size,
+                conf.adaptive_sizing,
+            )),
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Rewind local chain state to the given `checkpoint`, i.e. the last upgrade acknowledged
+    /// downstream, so that every upgrade beyond it is emitted again. Node errors are retried,
+    /// since syncing from a partially rewound cache would leave a gap downstream.
+    pub async fn resume_from(&self, checkpoint: BlockRecord) -> Result<(), ResumeError> {
+        let mut cache = self.cache.lock().await;
+        let Some(best_block) = cache.get_best_block().await else {
+            return Ok(());
+        };
+        trace!(target: "chain_sync", "Resuming from checkpoint [{}], height: {}", checkpoint.id, checkpoint.height);
+
+        // Blocks rolled back locally whose rollback was never acknowledged are gone from the
+        // cache, so they have to be fetched again in order to be unapplied downstream.
+        let mut tip = checkpoint;
+        let mut replay = Vec::new();
+        while !cache.exists(tip.id).await {
+            if replay.len() as u32 >= ERGO_MAX_ROLLBACK_DEPTH {
+                return Err(ResumeError::TooFarFromLocalChain(ERGO_MAX_ROLLBACK_DEPTH));
+            }
+            match self.client.get_full_blocks(vec![tip.id]).await {
+                Ok(blocks) if !blocks.is_empty() => {
+                    let blk = Block::from(blocks.into_iter().next().unwrap());
+                    tip = BlockRecord {
+                        id: blk.parent_id,
+                        height: blk.height.saturating_sub(1),
+                    };
+                    replay.push(ChainUpgrade::RollBackward(blk));
+                }
+                Ok(_) => return Err(ResumeError::UnknownBlock(tip.id)),
+                Err(e) => {
+                    error!(target: "chain_sync", "Failed to fetch unacknowledged block [{}], retrying: {:?}", tip.id, e);
+                    Delay::new(Duration::from_millis(self.conf.throttle_ms)).await;
+                }
+            }
+        }
+
+        // Blocks appended to the cache but never acknowledged are dropped, so that they're
+        // fetched and rolled forward again.
+        let mut best_block_id = best_block.id;
+        while best_block_id != tip.id {
+            if cache.take_best_block().await.is_none() {
+                break;
+            }
+            match cache.get_best_block().await {
+                Some(blk) => best_block_id = blk.id,
+                None => break,
+            }
         }
+
+        self.state.lock().await.next_height = max(tip.height, self.starting_height);
+        *self.pending.lock().await = replay;
+        Ok(())
     }
 
     #[allow(clippy::await_holding_refcell_ref)]
     /// Try acquiring next batch of upgrades from the network.
     /// `None` is returned when no upgrades are available at the moment.
     pub async fn try_upgrade(&self) -> Option<Vec<ChainUpgrade>> {
+        let pending = mem::take(&mut *self.pending.lock().await);
+        if !pending.is_empty() {
+            return Some(pending);
+        }
+
         let next_height = { self.state.lock().await.next_height };
         trace!(target: "chain_sync", "Processing height batch starting at [{}]", next_height);
 
@@ -171,64 +266,31 @@ where
             return None;
         }
 
-        match self
+        let (batch_size, chunk_size) = {
+            let sizing = self.sizing.lock().await;
+            (sizing.batch_size(), sizing.chunk_size())
+        };
+        // Near the tip, batches are small and blocks are fetched one chunk at a time.
+        let prefetch = if best_height - next_height < batch_size {
+            1
+        } else {
+            self.conf.prefetch
+        };
+        let started_at = Instant::now();
+        let api_blocks = match self
             .client
-            .get_blocks_batch(next_height, self.batch_size, self.chunk_size)
+            .get_blocks_batch(next_height, batch_size, chunk_size, prefetch)
             .await
         {
             Ok(api_blocks) => {
-                trace!(target: "chain_sync", "Got {} blocks from API", api_blocks.len());
-                let mut upgrades = Vec::new();
-                for api_blk in api_blocks {
-                    let block_height = api_blk.header.height;
-                    // If the returned block height is less than the requested next_height,
-                    // it means we're getting a top block because we've exceeded the chain tip.
-                    // Treat this as no new block scenario and break.
-                    if block_height < next_height {
-                        trace!(target: "chain_sync", "Received block at height [{}] which is less than requested next_height [{}]. No new block scenario.", block_height, next_height);
-                        break;
-                    }
-
-                    let mut cache = self.cache.lock().await;
-
-                    // Check if we already have this block
-                    if cache.exists(api_blk.header.id).await {
-                        trace!(
-                            target: "chain_sync",
-                            "Skipping block [{}], already in cache at height: {}",
-                            api_blk.header.id,
-                            block_height
-                        );
-                        self.state.lock().await.upgrade();
-                        continue;
-                    }
-
-                    let parent_id = api_blk.header.parent_id;
-                    let linked = cache.exists(parent_id).await;
-                    if linked || block_height == self.starting_height {
-                        trace!(target: "chain_sync", "Chain is linked, upgrading ..");
-                        let blk = Block::from(api_blk);
-                        cache.append_block(blk.clone()).await;
-                        self.state.lock().await.upgrade();
-                        upgrades.push(ChainUpgrade::RollForward(blk));
-                    } else {
-                        // Local chain does not link anymore
-                        trace!(target: "chain_sync", "Chain does not link, downgrading ..");
-                        if let Some(discarded_blk) = cache.take_best_block().await {
-                            self.state.lock().await.downgrade();
-                            upgrades.push(ChainUpgrade::RollBackward(discarded_blk));
-                            // Stop processing batch after rollback
-                            break;
-                        }
-                    }
-                }
-                if !upgrades.is_empty() {
-                    Some(upgrades)
-                } else {
-                    None
-                }
+                self.sizing
+                    .lock()
+                    .await
+                    .on_success(started_at.elapsed(), api_blocks.len());
+                api_blocks
             }
             Err(e) => {
+                self.sizing.lock().await.on_error(&e);
                 println!("try_upgrade error details: {:?}", e);
                 error!(target: "chain_sync", "try_upgrade error details: {:?}", e);
 
@@ -243,15 +305,188 @@ where
                         error!(target: "chain_sync", "Unexpected error: {}", e);
                     }
                 }
-                None
+                return None;
+            }
+        };
+        trace!(target: "chain_sync", "Got {} blocks from API", api_blocks.len());
+        let mut upgrades = Vec::new();
+        let linked = self
+            .roll_forward(api_blocks, next_height, &mut upgrades)
+            .await;
+        if !linked {
+            // Local chain does not link anymore
+            trace!(target: "chain_sync", "Chain does not link, downgrading ..");
+            let mut cache = self.cache.lock().await;
+            let best_block_id = cache.get_best_block().await.map(|blk| blk.id);
+            // A node switching chains between requests may report the local tip as being on its
+            // chain, in which case there would be nothing to roll back.
+            let fork_point = self
+                .find_fork_point(&mut *cache)
+                .await
+                .filter(|fork_point| Some(fork_point.id) != best_block_id);
+            match fork_point {
+                Some(fork_point) => {
+                    trace!(target: "chain_sync", "Fork point is [{}], height: {}", fork_point.id, fork_point.height);
+                    while let Some(best_block) = cache.get_best_block().await {
+                        if best_block.id == fork_point.id {
+                            break;
+                        }
+                        match cache.take_best_block().await {
+                            Some(discarded_blk) => {
+                                upgrades.push(ChainUpgrade::RollBackward(discarded_blk))
+                            }
+                            None => break,
+                        }
+                    }
+                    drop(cache);
+                    let next_height = fork_point.height + 1;
+                    self.state.lock().await.next_height = next_height;
+                    // The new branch is fetched right away, so that the whole reorg is emitted
+                    // as one batch.
+                    match self
+                        .client
+                        .get_blocks_batch(next_height, batch_size, chunk_size, prefetch)
+                        .await
+                    {
+                        Ok(api_blocks) => {
+                            self.roll_forward(api_blocks, next_height, &mut upgrades)
+                                .await;
+                        }
+                        Err(e) => {
+                            self.sizing.lock().await.on_error(&e);
+                            error!(target: "chain_sync", "Failed to fetch new branch at height {}: {}", next_height, e);
+                        }
+                    }
+                }
+                None => {
+                    // Fall back to rolling back one block at a time.
+                    if let Some(discarded_blk) = cache.take_best_block().await {
+                        self.state.lock().await.downgrade();
+                        upgrades.push(ChainUpgrade::RollBackward(discarded_blk));
+                    }
+                }
             }
         }
+        if !upgrades.is_empty() {
+            Some(upgrades)
+        } else {
+            None
+        }
+    }
+
+    /// Appends the given blocks to the local chain as long as they link to it, adding a roll
+    /// forward to `upgrades` for each. Returns `false` if a block does not link.
+    async fn roll_forward(
+        &self,
+        api_blocks: Vec<FullBlock>,
+        next_height: u32,
+        upgrades: &mut Vec<ChainUpgrade>,
+    ) -> bool {
+        for api_blk in api_blocks {
+            let block_height = api_blk.header.height;
+            // If the returned block height is less than the requested next_height,
+            // it means we're getting a top block because we've exceeded the chain tip.
+            // Treat this as no new block scenario and break.
+            if block_height < next_height {
+                trace!(target: "chain_sync", "Received block at height [{}] which is less than requested next_height [{}]. No new block scenario.", block_height, next_height);
+                break;
+            }
+
+            let mut cache = self.cache.lock().await;
+
+            // Check if we already have this block
+            if cache.exists(api_blk.header.id).await {
+                trace!(
+                    target: "chain_sync",
+                    "Skipping block [{}], already in cache at height: {}",
+                    api_blk.header.id,
+                    block_height
+                );
+                self.state.lock().await.upgrade();
+                continue;
+            }
+
+            let parent_id = api_blk.header.parent_id;
+            let linked = cache.exists(parent_id).await;
+            if linked || block_height == self.starting_height {
+                trace!(target: "chain_sync", "Chain is linked, upgrading ..");
+                let blk = Block::from(api_blk);
+                cache.append_block(blk.clone()).await;
+                self.state.lock().await.upgrade();
+                upgrades.push(ChainUpgrade::RollForward(blk));
+            } else {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Finds the most recent cached block which is also on the node's best chain, by comparing
+    /// cached ancestors of the best block against header IDs of the node's chain slice, one
+    /// window of [`FORK_SEARCH_WINDOW`] blocks at a time.
+    async fn find_fork_point(&self, cache: &mut TCache) -> Option<BlockRecord> {
+        let mut next_window = cache.get_best_block().await;
+        while let Some(top) = next_window.take() {
+            let mut window = vec![top];
+            while window.len() < FORK_SEARCH_WINDOW {
+                match cached_parent(cache, window.last().unwrap()).await {
+                    Some(parent) => window.push(parent),
+                    None => break,
+                }
+            }
+            let from_height = window.last().unwrap().height;
+            let to_height = window[0].height;
+            // The lower bound of a chain slice is exclusive.
+            let node_chain: HashMap<u32, BlockId> = match self
+                .client
+                .get_chain_slice(from_height.saturating_sub(1), to_height)
+                .await
+            {
+                Ok(headers) => headers.into_iter().map(|h| (h.height, h.id)).collect(),
+                Err(e) => {
+                    error!(target: "chain_sync", "Failed to fetch chain slice from {} to {}: {}", from_height, to_height, e);
+                    return None;
+                }
+            };
+            if let Some(fork_point) = window
+                .iter()
+                .find(|blk| node_chain.get(&blk.height) == Some(&blk.id))
+            {
+                return Some(fork_point.clone());
+            }
+            if window.len() == FORK_SEARCH_WINDOW {
+                next_window = cached_parent(cache, window.last().unwrap()).await;
+            }
+        }
+        error!(target: "chain_sync", "No common ancestor with the node's chain among cached blocks");
+        None
     }
 }
 
-pub fn chain_sync_stream<'a, TClient, TCache>(
+/// Number of cached blocks compared against the node's chain per chain slice request.
+const FORK_SEARCH_WINDOW: usize = 16;
+
+/// The parent of the given block, if it's cached as well.
+async fn cached_parent<TCache: ChainCache>(
+    cache: &mut TCache,
+    blk: &BlockRecord,
+) -> Option<BlockRecord> {
+    let parent_id = cache.get_parent_id(blk.id).await?;
+    if cache.exists(parent_id).await {
+        Some(BlockRecord {
+            id: parent_id,
+            height: blk.height - 1,
+        })
+    } else {
+        None
+    }
+}
+
+/// Upgrades batch by batch, as acquired by [`ChainSync::try_upgrade`]. The roll backwards of
+/// a reorg share their batch with the part of the new branch fetched along with them.
+pub fn chain_sync_batch_stream<'a, TClient, TCache>(
     chain_sync: ChainSync<'a, TClient, TCache>,
-) -> impl Stream<Item = ChainUpgrade> + 'a
+) -> impl Stream<Item = Vec<ChainUpgrade>> + 'a
 where
     TClient: ErgoNetwork + Send + Sync + Unpin,
     TCache: ChainCache + Unpin + 'a,
@@ -260,12 +495,352 @@ where
     stream! {
         loop {
             if let Some(upgrades) = cs.try_upgrade().await {
-                for upg in upgrades {
-                    yield upg;
-                }
+                yield upgrades;
             } else {
-                Delay::new(Duration::from_millis(cs.throttle_ms)).await;
+                Delay::new(Duration::from_millis(cs.conf.throttle_ms)).await;
+            }
+        }
+    }
+}
+
+pub fn chain_sync_stream<'a, TClient, TCache>(
+    chain_sync: ChainSync<'a, TClient, TCache>,
+) -> impl Stream<Item = ChainUpgrade> + 'a
+where
+    TClient: ErgoNetwork + Send + Sync + Unpin,
+    TCache: ChainCache + Unpin + 'a,
+{
+    chain_sync_batch_stream(chain_sync).flat_map(futures::stream::iter)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use ergo_lib::ergo_chain_types::{BlockId, Digest32, Header};
+    use sigma_test_util::force_any_val;
+
+    use crate::cache::chain_cache::{ChainCache, InMemoryCache};
+    use crate::checkpoint::{Checkpoint, InMemoryCheckpoint};
+    use crate::client::model::{BlockExtension, BlockTransaction, FullBlock};
+    use crate::client::node::{ErgoNetwork, Error};
+    use crate::model::{Block, BlockRecord};
+    use crate::{ChainSync, ChainSyncConf, ChainUpgrade};
+
+    /// Node whose best chain can be replaced at will.
+    struct FakeNetwork {
+        chain: Mutex<Vec<FullBlock>>,
+        /// Blocks off the best chain, which are still served by ID.
+        orphaned: Mutex<Vec<FullBlock>>,
+        /// Chain reported by chain slices instead of the best one, as by a node switching
+        /// chains between requests.
+        stale_slice: Mutex<Option<Vec<FullBlock>>>,
+    }
+
+    impl FakeNetwork {
+        fn new(chain: Vec<FullBlock>) -> Self {
+            Self {
+                chain: Mutex::new(chain),
+                orphaned: Mutex::new(vec![]),
+                stale_slice: Mutex::new(None),
+            }
+        }
+
+        fn blocks_between(&self, from_height: u32, to_height: u32) -> Vec<FullBlock> {
+            between(&self.chain.lock().unwrap(), from_height, to_height)
+        }
+    }
+
+    fn between(chain: &[FullBlock], from_height: u32, to_height: u32) -> Vec<FullBlock> {
+        chain
+            .iter()
+            .filter(|blk| blk.header.height >= from_height && blk.header.height <= to_height)
+            .cloned()
+            .collect()
+    }
+
+    #[async_trait]
+    impl ErgoNetwork for FakeNetwork {
+        async fn get_blocks_range(
+            &self,
+            from_height: u32,
+            to_height: u32,
+        ) -> Result<Vec<BlockId>, Error> {
+            Ok(self
+                .blocks_between(from_height, to_height)
+                .into_iter()
+                .map(|blk| blk.header.id)
+                .collect())
+        }
+
+        async fn get_chain_slice(
+            &self,
+            from_height: u32,
+            to_height: u32,
+        ) -> Result<Vec<BlockRecord>, Error> {
+            // Like the node, excludes `from_height`.
+            let blocks = match &*self.stale_slice.lock().unwrap() {
+                Some(chain) => between(chain, from_height + 1, to_height),
+                None => self.blocks_between(from_height + 1, to_height),
+            };
+            Ok(blocks
+                .into_iter()
+                .map(|blk| BlockRecord {
+                    id: blk.header.id,
+                    height: blk.header.height,
+                })
+                .collect())
+        }
+
+        async fn get_best_height(&self) -> Result<u32, Error> {
+            Ok(self
+                .chain
+                .lock()
+                .unwrap()
+                .last()
+                .map_or(0, |blk| blk.header.height))
+        }
+
+        async fn fetch_mempool(
+            &self,
+            _offset: usize,
+            _limit: usize,
+        ) -> Result<Vec<BlockTransaction>, Error> {
+            Ok(vec![])
+        }
+
+        async fn get_full_blocks(&self, block_ids: Vec<BlockId>) -> Result<Vec<FullBlock>, Error> {
+            let chain = self.chain.lock().unwrap();
+            let orphaned = self.orphaned.lock().unwrap();
+            Ok(block_ids
+                .iter()
+                .filter_map(|id| {
+                    chain
+                        .iter()
+                        .chain(orphaned.iter())
+                        .find(|blk| blk.header.id == *id)
+                        .cloned()
+                })
+                .collect())
+        }
+
+        async fn get_block_extension(&self, _block_id: BlockId) -> Result<BlockExtension, Error> {
+            Err(Error::NoBlock)
+        }
+    }
+
+    fn block_id(seed: u8) -> BlockId {
+        BlockId(Digest32::from([seed; 32]))
+    }
+
+    /// Blocks at heights `from_height..=to_height` on top of `parent_id`, with IDs derived from
+    /// `fork` and their heights.
+    fn chain(parent_id: BlockId, from_height: u32, to_height: u32, fork: u8) -> Vec<FullBlock> {
+        let mut parent_id = parent_id;
+        (from_height..=to_height)
+            .map(|height| {
+                let id = block_id(fork + height as u8);
+                let blk = FullBlock {
+                    header: Header {
+                        id,
+                        parent_id,
+                        height,
+                        ..force_any_val::<Header>()
+                    },
+                    transactions: vec![],
+                    size: None,
+                };
+                parent_id = id;
+                blk
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn deep_reorg_is_emitted_in_one_batch() {
+        let main_chain = chain(block_id(0), 1, 5, 0);
+        let network = FakeNetwork::new(main_chain.clone());
+        let conf = ChainSyncConf {
+            batch_size: 10,
+            chunk_size: 2,
+            adaptive_sizing: None,
+            prefetch: 2,
+            throttle_ms: 0,
+        };
+        let chain_sync = ChainSync::init(1, &network, InMemoryCache::new(), None, conf).await;
+        let upgrades = chain_sync.try_upgrade().await.unwrap();
+        assert_eq!(upgrades.len(), 5);
+
+        // The node switches to a longer branch forking off at height 2.
+        let mut fork = main_chain[..2].to_vec();
+        fork.extend(chain(main_chain[1].header.id, 3, 6, 100));
+        *network.chain.lock().unwrap() = fork.clone();
+
+        let upgrades = chain_sync.try_upgrade().await.unwrap();
+        let summary: Vec<_> = upgrades
+            .iter()
+            .map(|upgrade| match upgrade {
+                ChainUpgrade::RollBackward(blk) => (false, blk.id),
+                ChainUpgrade::RollForward(blk) => (true, blk.id),
+            })
+            .collect();
+        let expected: Vec<_> = main_chain[2..]
+            .iter()
+            .rev()
+            .map(|blk| (false, blk.header.id))
+            .chain(fork[2..].iter().map(|blk| (true, blk.header.id)))
+            .collect();
+        assert_eq!(summary, expected);
+        assert!(chain_sync.try_upgrade().await.is_none());
+    }
+
+    const CONF: ChainSyncConf = ChainSyncConf {
+        batch_size: 10,
+        chunk_size: 2,
+        adaptive_sizing: None,
+        prefetch: 1,
+        throttle_ms: 0,
+    };
+
+    /// Acknowledges the given upgrades the way downstream does, asserting that each one applies
+    /// on top of the tip left by the previous one.
+    async fn ack_contiguous(checkpoint: &mut InMemoryCheckpoint, upgrades: Vec<ChainUpgrade>) {
+        for upgrade in upgrades {
+            let tip = checkpoint.get().await.unwrap();
+            match &upgrade {
+                ChainUpgrade::RollForward(blk) => assert_eq!(blk.parent_id, tip.id),
+                ChainUpgrade::RollBackward(blk) => assert_eq!(blk.id, tip.id),
+            }
+            checkpoint.set(upgrade.resulting_tip()).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_replays_unacknowledged_roll_forwards() {
+        let main_chain = chain(block_id(0), 1, 5, 0);
+        let network = FakeNetwork::new(main_chain.clone());
+        let mut cache = InMemoryCache::new();
+        for blk in &main_chain {
+            cache.append_block(Block::from(blk.clone())).await;
+        }
+        // Only blocks up to height 3 were acknowledged before the restart.
+        let mut checkpoint = InMemoryCheckpoint::new();
+        checkpoint
+            .set(BlockRecord {
+                id: main_chain[2].header.id,
+                height: 3,
+            })
+            .await;
+
+        let chain_sync = ChainSync::init(1, &network, cache, None, CONF).await;
+        chain_sync
+            .resume_from(checkpoint.get().await.unwrap())
+            .await
+            .unwrap();
+        let upgrades = chain_sync.try_upgrade().await.unwrap();
+        assert_eq!(upgrades.len(), 2);
+        ack_contiguous(&mut checkpoint, upgrades).await;
+        assert_eq!(checkpoint.get().await.unwrap().id, main_chain[4].header.id);
+        assert!(chain_sync.try_upgrade().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn resume_replays_unacknowledged_rollbacks() {
+        let main_chain = chain(block_id(0), 1, 5, 0);
+        let mut fork = main_chain[..3].to_vec();
+        fork.extend(chain(main_chain[2].header.id, 4, 6, 100));
+        let network = FakeNetwork::new(fork.clone());
+        *network.orphaned.lock().unwrap() = main_chain[3..].to_vec();
+        // Blocks 4 and 5 were rolled back in the cache, but the rollback was never acknowledged.
+        let mut cache = InMemoryCache::new();
+        for blk in &main_chain[..3] {
+            cache.append_block(Block::from(blk.clone())).await;
+        }
+        let mut checkpoint = InMemoryCheckpoint::new();
+        checkpoint
+            .set(BlockRecord {
+                id: main_chain[4].header.id,
+                height: 5,
+            })
+            .await;
+
+        let chain_sync = ChainSync::init(1, &network, cache, None, CONF).await;
+        chain_sync
+            .resume_from(checkpoint.get().await.unwrap())
+            .await
+            .unwrap();
+        let mut upgrades = chain_sync.try_upgrade().await.unwrap();
+        assert!(upgrades
+            .iter()
+            .all(|u| matches!(u, ChainUpgrade::RollBackward(_))));
+        upgrades.extend(chain_sync.try_upgrade().await.unwrap());
+        assert_eq!(upgrades.len(), 5);
+        ack_contiguous(&mut checkpoint, upgrades).await;
+        assert_eq!(checkpoint.get().await.unwrap().id, fork[5].header.id);
+        assert!(chain_sync.try_upgrade().await.is_none());
+    }
+
+    /// Kind and ID of every upgrade.
+    fn summary(upgrades: &[ChainUpgrade]) -> Vec<(bool, BlockId)> {
+        upgrades
+            .iter()
+            .map(|upgrade| match upgrade {
+                ChainUpgrade::RollBackward(blk) => (false, blk.id),
+                ChainUpgrade::RollForward(blk) => (true, blk.id),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reorg_forking_off_the_oldest_cached_block_is_emitted_in_one_batch() {
+        let main_chain = chain(block_id(0), 1, 5, 0);
+        let network = FakeNetwork::new(main_chain.clone());
+        let chain_sync = ChainSync::init(1, &network, InMemoryCache::new(), None, CONF).await;
+        assert_eq!(chain_sync.try_upgrade().await.unwrap().len(), 5);
+
+        // The node switches to a branch forking off at height 1, the oldest cached block.
+        let mut fork = main_chain[..1].to_vec();
+        fork.extend(chain(main_chain[0].header.id, 2, 6, 100));
+        *network.chain.lock().unwrap() = fork.clone();
+
+        let expected: Vec<_> = main_chain[1..]
+            .iter()
+            .rev()
+            .map(|blk| (false, blk.header.id))
+            .chain(fork[1..].iter().map(|blk| (true, blk.header.id)))
+            .collect();
+        assert_eq!(summary(&chain_sync.try_upgrade().await.unwrap()), expected);
+        assert!(chain_sync.try_upgrade().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn tip_reported_on_the_node_chain_is_rolled_back_one_block_at_a_time() {
+        let main_chain = chain(block_id(0), 1, 5, 0);
+        let network = FakeNetwork::new(main_chain.clone());
+        let chain_sync = ChainSync::init(1, &network, InMemoryCache::new(), None, CONF).await;
+        assert_eq!(chain_sync.try_upgrade().await.unwrap().len(), 5);
+
+        // The node serves a branch forking off at height 3, while its chain slices still show
+        // the former chain, tip included.
+        let mut fork = main_chain[..3].to_vec();
+        fork.extend(chain(main_chain[2].header.id, 4, 7, 100));
+        *network.chain.lock().unwrap() = fork.clone();
+        *network.stale_slice.lock().unwrap() = Some(main_chain.clone());
+
+        let mut upgrades = vec![];
+        for _ in 0..10 {
+            match chain_sync.try_upgrade().await {
+                Some(batch) => upgrades.extend(batch),
+                None => break,
             }
         }
+        let expected: Vec<_> = main_chain[3..]
+            .iter()
+            .rev()
+            .map(|blk| (false, blk.header.id))
+            .chain(fork[3..].iter().map(|blk| (true, blk.header.id)))
+            .collect();
+        assert_eq!(summary(&upgrades), expected);
     }
 }
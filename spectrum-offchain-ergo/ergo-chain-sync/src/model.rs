@@ -1,4 +1,4 @@
-use ergo_lib::ergo_chain_types::BlockId;
+use ergo_lib::ergo_chain_types::{BlockId, Header};
 use serde::{Deserialize, Serialize};
 
 use crate::client::model::{BlockTransaction, FullBlock};
@@ -10,6 +10,10 @@ pub struct Block {
     pub height: u32,
     pub timestamp: u64,
     pub transactions: Vec<BlockTransaction>,
+    /// Missing for blocks cached before headers were kept.
+    pub header: Option<Header>,
+    /// Size of the block in bytes, if reported by the node.
+    pub size: Option<u32>,
 }
 
 impl From<FullBlock> for Block {
@@ -20,6 +24,8 @@ impl From<FullBlock> for Block {
             height: fb.header.height,
             timestamp: fb.header.timestamp,
             transactions: fb.transactions,
+            size: fb.size,
+            header: Some(fb.header),
         }
     }
 }
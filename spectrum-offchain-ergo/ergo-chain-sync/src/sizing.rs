@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use log::info;
+use serde::Deserialize;
+
+use crate::client::node::Error;
+
+/// Bounds within which batch and chunk sizes are adjusted at runtime.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct AdaptiveSizingConf {
+    pub min_batch_size: u32,
+    pub max_batch_size: u32,
+    pub min_chunk_size: usize,
+    pub max_chunk_size: usize,
+    /// Sizes grow while full batches are fetched within this time.
+    pub fast_response_ms: u64,
+}
+
+/// Batch and chunk sizes of chain sync requests. Both are halved when the node fails to serve
+/// them, and grow by a quarter while it serves full batches fast. They stay fixed if no
+/// [`AdaptiveSizingConf`] is given.
+#[derive(Debug, Clone)]
+pub struct BatchSizing {
+    batch_size: u32,
+    chunk_size: usize,
+    conf: Option<AdaptiveSizingConf>,
+}
+
+impl BatchSizing {
+    pub fn new(batch_size: u32, chunk_size: usize, conf: Option<AdaptiveSizingConf>) -> Self {
+        let mut sizing = Self {
+            batch_size,
+            chunk_size,
+            conf,
+        };
+        if let Some(conf) = conf {
+            sizing.batch_size = batch_size.clamp(conf.min_batch_size, conf.max_batch_size);
+            sizing.chunk_size = chunk_size.clamp(conf.min_chunk_size, conf.max_chunk_size);
+        }
+        sizing
+    }
+
+    pub fn batch_size(&self) -> u32 {
+        self.batch_size
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Grows sizes if a batch of `num_blocks` was fetched within `elapsed` quickly enough.
+    /// Batches cut short, e.g. near the chain tip, tell nothing about the node's capacity.
+    pub fn on_success(&mut self, elapsed: Duration, num_blocks: usize) {
+        if let Some(conf) = self.conf {
+            let full = num_blocks >= self.batch_size as usize;
+            if full && elapsed < Duration::from_millis(conf.fast_response_ms) {
+                self.resize(
+                    (self.batch_size + (self.batch_size / 4).max(1)).min(conf.max_batch_size),
+                    (self.chunk_size + (self.chunk_size / 4).max(1)).min(conf.max_chunk_size),
+                    "fast response",
+                );
+            }
+        }
+    }
+
+    /// Halves sizes if the given error suggests the request was too heavy for the node.
+    pub fn on_error(&mut self, error: &Error) {
+        if let Some(conf) = self.conf {
+            if error.is_overload() {
+                self.resize(
+                    (self.batch_size / 2).max(conf.min_batch_size),
+                    (self.chunk_size / 2).max(conf.min_chunk_size),
+                    &error.to_string(),
+                );
+            }
+        }
+    }
+
+    fn resize(&mut self, batch_size: u32, chunk_size: usize, reason: &str) {
+        if batch_size != self.batch_size || chunk_size != self.chunk_size {
+            info!(
+                target: "chain_sync",
+                "Resizing batches from {} to {} blocks, chunks from {} to {} blocks ({})",
+                self.batch_size, batch_size, self.chunk_size, chunk_size, reason
+            );
+            self.batch_size = batch_size;
+            self.chunk_size = chunk_size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::client::node::Error;
+    use crate::sizing::{AdaptiveSizingConf, BatchSizing};
+
+    const CONF: AdaptiveSizingConf = AdaptiveSizingConf {
+        min_batch_size: 4,
+        max_batch_size: 100,
+        min_chunk_size: 1,
+        max_chunk_size: 10,
+        fast_response_ms: 1000,
+    };
+
+    #[test]
+    fn sizes_are_halved_on_payload_errors_down_to_bounds() {
+        let mut sizing = BatchSizing::new(20, 8, Some(CONF));
+        sizing.on_error(&Error::Overloaded("payload too large".to_string()));
+        assert_eq!((sizing.batch_size(), sizing.chunk_size()), (10, 4));
+        for _ in 0..10 {
+            sizing.on_error(&Error::Overloaded("payload too large".to_string()));
+        }
+        assert_eq!((sizing.batch_size(), sizing.chunk_size()), (4, 1));
+    }
+
+    /// Reports a full batch fetched within the given time.
+    fn on_full_batch(sizing: &mut BatchSizing, elapsed_ms: u64) {
+        let num_blocks = sizing.batch_size() as usize;
+        sizing.on_success(Duration::from_millis(elapsed_ms), num_blocks);
+    }
+
+    #[test]
+    fn sizes_grow_on_fast_responses_up_to_bounds() {
+        let mut sizing = BatchSizing::new(20, 4, Some(CONF));
+        on_full_batch(&mut sizing, 2000);
+        assert_eq!((sizing.batch_size(), sizing.chunk_size()), (20, 4));
+        on_full_batch(&mut sizing, 100);
+        assert_eq!((sizing.batch_size(), sizing.chunk_size()), (25, 5));
+        for _ in 0..20 {
+            on_full_batch(&mut sizing, 100);
+        }
+        assert_eq!((sizing.batch_size(), sizing.chunk_size()), (100, 10));
+    }
+
+    #[test]
+    fn sizes_are_kept_on_partial_batches() {
+        let mut sizing = BatchSizing::new(20, 4, Some(CONF));
+        sizing.on_success(Duration::from_millis(100), 3);
+        assert_eq!((sizing.batch_size(), sizing.chunk_size()), (20, 4));
+    }
+
+    #[test]
+    fn sizes_are_kept_on_other_errors_or_without_conf() {
+        let mut sizing = BatchSizing::new(20, 4, Some(CONF));
+        sizing.on_error(&Error::NoBlock);
+        sizing.on_error(&Error::UnsuccessfulRequest("not found".to_string()));
+        assert_eq!((sizing.batch_size(), sizing.chunk_size()), (20, 4));
+
+        let mut sizing = BatchSizing::new(200, 40, None);
+        sizing.on_error(&Error::Overloaded("payload too large".to_string()));
+        on_full_batch(&mut sizing, 100);
+        assert_eq!((sizing.batch_size(), sizing.chunk_size()), (200, 40));
+    }
+
+    #[test]
+    fn initial_sizes_are_clamped_to_bounds() {
+        let sizing = BatchSizing::new(500, 0, Some(CONF));
+        assert_eq!((sizing.batch_size(), sizing.chunk_size()), (100, 1));
+    }
+}
@@ -55,6 +55,8 @@ async fn test_client<C: ChainCache>(mut client: C) {
             height,
             timestamp,
             transactions,
+            header: None,
+            size: None,
         };
         blocks.push(block.clone());
         height += 1;
@@ -1,57 +1,289 @@
 use futures::stream::StreamExt;
-use futures::{stream, Stream};
+use futures::{future, stream, Stream};
 use std::sync::Arc;
+use std::{iter, slice};
+use tokio::sync::Mutex;
 
+use ergo_chain_sync::cache::chain_cache::ChainCache;
+use ergo_chain_sync::checkpoint::Checkpoint;
+use ergo_chain_sync::client::node::ErgoNetwork;
 use ergo_chain_sync::ChainUpgrade;
 use ergo_mempool_sync::MempoolUpdate;
-use log::info;
+use log::{info, warn};
 
-use crate::models::block_event::BlockEvent;
-use crate::models::mempool_event::MempoolEvent;
+use crate::finality::Finality;
+use crate::models::block_event::{BlockEvent, Reorg};
+use crate::models::dead_letter::DeadLetter;
+use crate::models::encoding::Encoder;
+use crate::models::header_event::HeaderEvent;
 use crate::models::tx_event::TxEvent;
-use async_std::task::spawn_blocking;
-use kafka::producer::{Producer, Record};
+use crate::reorg::ReorgTracker;
+use crate::routing::Router;
+use crate::sink::sequencer::Sequencer;
+use crate::sink::{
+    send_batch_until_acked, send_until_acked, EventSink, RecordBatch, SinkRecord, BLOCK_ID_HEADER,
+    EVENT_TYPE_HEADER, HEIGHT_HEADER,
+};
 
 pub fn block_event_source<S>(
     upstream: S,
-    producer: Producer,
+    sink: Arc<dyn EventSink>,
+    sequencer: Arc<dyn Sequencer>,
     topic: String,
+    encoder: Encoder,
 ) -> impl Stream<Item = ChainUpgrade>
 where
     S: Stream<Item = ChainUpgrade>,
 {
-    let topic = Arc::new(tokio::sync::Mutex::new(topic));
-    let producer = Arc::new(std::sync::Mutex::new(producer));
     upstream.then(move |ev| {
         let topic = topic.clone();
-        let producer = producer.clone();
+        let sink = sink.clone();
+        let sequencer = sequencer.clone();
         let ev_clone = ev.clone();
         async move {
             let block_event = BlockEvent::from(ev_clone);
-            let block_id: String = match block_event.clone() {
-                BlockEvent::BlockApply { id, .. } | BlockEvent::BlockUnapply { id, .. } => id,
+            let event_type = block_event.event_type();
+            let (block_id, height) = match block_event.clone() {
+                BlockEvent::BlockApply { id, height, .. }
+                | BlockEvent::BlockUnapply { id, height, .. }
+                | BlockEvent::BlockFinalized { id, height, .. } => (id, height),
+                BlockEvent::ReorgDetected(r) | BlockEvent::ReorgCompleted(r) => {
+                    (r.common_ancestor_id, r.common_ancestor_height)
+                }
             };
-            let value = serde_json::to_string(&block_event).unwrap();
-            let topic = topic.clone().lock().await.clone();
-            spawn_blocking(move || {
-                let rec: &Record<String, String> =
-                    &Record::from_key_value(topic.as_str(), block_id.clone(), value.clone());
-                info!("Block value is: ${:?}", value.clone());
-                info!("Got new block. Key: ${:?}", block_id);
-                producer.lock().unwrap().send(rec).unwrap();
-                info!("New block processed by kafka. Key: ${:?}", block_id);
+            info!("Block value is: ${:?}", block_event);
+            info!("Got new block. Key: ${:?}", block_id);
+            let value = encoder.block_event(block_event);
+            let mut record = SinkRecord::new(topic, block_id.clone(), value)
+                .with_header(EVENT_TYPE_HEADER, event_type)
+                .with_header(HEIGHT_HEADER, height)
+                .with_header(BLOCK_ID_HEADER, &block_id);
+            sequencer.stamp(slice::from_mut(&mut record)).await;
+            send_until_acked(&*sink, record).await;
+            info!("New block processed by sink. Key: ${:?}", block_id);
+            ev
+        }
+    })
+}
+
+/// Publishes `BlockFinalized` to `topic` for every block the `finality` reports final, right
+/// before the batch of upgrades that made it final is passed on. Batches are kept apart, and
+/// passed on as they are if no `finality` is given. Panics if a block which became final is
+/// missing from the chain cache, since its finalization could neither be published nor skipped
+/// without misleading consumers.
+pub fn finality_event_source<S, C>(
+    upstream: S,
+    sink: Arc<dyn EventSink>,
+    sequencer: Arc<dyn Sequencer>,
+    topic: String,
+    finality: Option<Finality<C>>,
+    encoder: Encoder,
+) -> impl Stream<Item = Vec<ChainUpgrade>>
+where
+    S: Stream<Item = Vec<ChainUpgrade>>,
+    C: ChainCache,
+{
+    let finality = finality.map(|finality| Arc::new(Mutex::new(finality)));
+    upstream.then(move |batch| {
+        let topic = topic.clone();
+        let sink = sink.clone();
+        let sequencer = sequencer.clone();
+        let finality = finality.clone();
+        async move {
+            let finality = match finality {
+                Some(finality) => finality,
+                None => return batch,
+            };
+            let mut finality = finality.lock().await;
+            let mut upgrades = Vec::new();
+            for ev in batch {
+                let (finalized, passed) = finality
+                    .apply(ev)
+                    .await
+                    .expect("Cannot determine final blocks");
+                upgrades.extend(passed);
+                for blk in finalized {
+                    let block_event = BlockEvent::finalized(&blk);
+                    let event_type = block_event.event_type();
+                    let block_id = base16::encode_lower(blk.id.0 .0.as_ref());
+                    info!("Block finalized. Key: ${:?}", block_id);
+                    let value = encoder.block_event(block_event);
+                    let mut record = SinkRecord::new(topic.clone(), block_id.clone(), value)
+                        .with_header(EVENT_TYPE_HEADER, event_type)
+                        .with_header(HEIGHT_HEADER, blk.height)
+                        .with_header(BLOCK_ID_HEADER, &block_id);
+                    sequencer.stamp(slice::from_mut(&mut record)).await;
+                    send_until_acked(&*sink, record).await;
+                }
+            }
+            upgrades
+        }
+    })
+}
+
+/// Publishes `ReorgDetected` to `topic` before the upgrades of a reorg are passed on, and
+/// `ReorgCompleted` once all of them are processed downstream. A reorg spans its roll backwards
+/// and the new branch fetched in the same batch, roll backwards being held back until a batch
+/// brings the new branch. Passes on everything if no `topic` is given.
+pub fn reorg_event_source<S>(
+    upstream: S,
+    sink: Arc<dyn EventSink>,
+    sequencer: Arc<dyn Sequencer>,
+    topic: Option<String>,
+    encoder: Encoder,
+) -> impl Stream<Item = ChainUpgrade>
+where
+    S: Stream<Item = Vec<ChainUpgrade>>,
+{
+    let tracking = topic.is_some();
+    let mut tracker = ReorgTracker::default();
+    let detection_sink = sink.clone();
+    let detection_sequencer = sequencer.clone();
+    let detection_topic = topic.clone();
+    upstream
+        .map(move |batch| {
+            if tracking {
+                tracker.apply(batch)
+            } else {
+                vec![(batch, None)]
+            }
+        })
+        .flat_map(stream::iter)
+        .then(move |(upgrades, reorg)| {
+            let topic = detection_topic.clone();
+            let sink = detection_sink.clone();
+            let sequencer = detection_sequencer.clone();
+            async move {
+                if let (Some(topic), Some(reorg)) = (topic, &reorg) {
+                    info!(
+                        "Reorg detected at height {}, {} blocks replaced by {}",
+                        reorg.common_ancestor_height,
+                        reorg.orphaned_block_ids.len(),
+                        reorg.replacing_block_ids.len()
+                    );
+                    let event = BlockEvent::ReorgDetected(reorg.clone());
+                    send_reorg_event(&*sink, &*sequencer, topic, event, reorg, encoder).await;
+                }
+                (upgrades, reorg)
+            }
+        })
+        .flat_map(move |(upgrades, reorg)| {
+            let sink = sink.clone();
+            let sequencer = sequencer.clone();
+            let topic = topic.clone();
+            // Pulled only once every upgrade of the reorg is processed downstream.
+            let completion = stream::once(async move {
+                if let (Some(topic), Some(reorg)) = (topic, reorg) {
+                    let event = BlockEvent::ReorgCompleted(reorg.clone());
+                    send_reorg_event(&*sink, &*sequencer, topic, event, &reorg, encoder).await;
+                }
             })
-            .await;
+            .filter_map(|_| future::ready(None));
+            stream::iter(upgrades).chain(completion)
+        })
+}
+
+async fn send_reorg_event(
+    sink: &dyn EventSink,
+    sequencer: &dyn Sequencer,
+    topic: String,
+    event: BlockEvent,
+    reorg: &Reorg,
+    encoder: Encoder,
+) {
+    let event_type = event.event_type();
+    let value = encoder.block_event(event);
+    let mut record = SinkRecord::new(topic, reorg.common_ancestor_id.clone(), value)
+        .with_header(EVENT_TYPE_HEADER, event_type)
+        .with_header(HEIGHT_HEADER, reorg.common_ancestor_height)
+        .with_header(BLOCK_ID_HEADER, &reorg.common_ancestor_id);
+    sequencer.stamp(slice::from_mut(&mut record)).await;
+    send_until_acked(sink, record).await;
+}
+
+/// Publishes the header of the block of every upgrade to `topic`, if one is given. Extensions
+/// are fetched from the `node` if `include_extension` is set.
+pub fn header_event_source<'a, S, N>(
+    upstream: S,
+    sink: Arc<dyn EventSink>,
+    sequencer: Arc<dyn Sequencer>,
+    topic: Option<String>,
+    node: &'a N,
+    include_extension: bool,
+    encoder: Encoder,
+) -> impl Stream<Item = ChainUpgrade> + 'a
+where
+    S: Stream<Item = ChainUpgrade> + 'a,
+    N: ErgoNetwork,
+{
+    upstream.then(move |ev| {
+        let topic = topic.clone();
+        let sink = sink.clone();
+        let sequencer = sequencer.clone();
+        async move {
+            let topic = match topic {
+                Some(topic) => topic,
+                None => return ev,
+            };
+            let blk = match &ev {
+                ChainUpgrade::RollForward(blk) | ChainUpgrade::RollBackward(blk) => blk,
+            };
+            let mut header_event = match HeaderEvent::new(&ev, encoder.network) {
+                Some(header_event) => header_event,
+                None => {
+                    warn!(
+                        "Header of block {} at height {} is unknown, skipping its header event",
+                        blk.id, blk.height
+                    );
+                    return ev;
+                }
+            };
+            if include_extension {
+                match node.get_block_extension(blk.id).await {
+                    Ok(extension) => header_event = header_event.with_extension(extension),
+                    Err(e) => warn!("Failed to fetch extension of block {}: {}", blk.id, e),
+                }
+            }
+            let block_id = header_event.header().id.clone();
+            let height = header_event.header().height;
+            let event_type = header_event.event_type();
+            let value = encoder.header_event(header_event);
+            let mut record = SinkRecord::new(topic, block_id.clone(), value)
+                .with_header(EVENT_TYPE_HEADER, event_type)
+                .with_header(HEIGHT_HEADER, height)
+                .with_header(BLOCK_ID_HEADER, &block_id);
+            sequencer.stamp(slice::from_mut(&mut record)).await;
+            send_until_acked(&*sink, record).await;
             ev
         }
     })
 }
 
-pub fn tx_event_source<S>(upstream: S) -> impl Stream<Item = TxEvent>
+/// Expands every upgrade into its tx events. Once all events of an upgrade are handled
+/// downstream, records they produced into the `batch` are published and the upgrade is
+/// recorded in the `checkpoint`.
+pub fn tx_event_source<S, C>(
+    upstream: S,
+    batch: RecordBatch,
+    checkpoint: Arc<Mutex<C>>,
+) -> impl Stream<Item = TxEvent>
 where
     S: Stream<Item = ChainUpgrade>,
+    C: Checkpoint,
 {
-    upstream.flat_map(|u| stream::iter(process_upgrade(u)))
+    upstream.flat_map(move |u| {
+        let batch = batch.clone();
+        let checkpoint = checkpoint.clone();
+        let tip = u.resulting_tip();
+        // Events are pulled one at a time, only after the previous one was fully handled, so
+        // the end of this stream is reached once every event of the upgrade is handled.
+        let commit = stream::once(async move {
+            batch.flush().await;
+            checkpoint.lock().await.set(tip).await;
+        })
+        .filter_map(|_| future::ready(None));
+        stream::iter(process_upgrade(u)).chain(commit)
+    })
 }
 
 fn process_upgrade(upgr: ChainUpgrade) -> Vec<TxEvent> {
@@ -82,32 +314,256 @@ fn process_upgrade(upgr: ChainUpgrade) -> Vec<TxEvent> {
 
 pub fn mempool_event_source<S>(
     upstream: S,
-    producer: Producer,
+    sink: Arc<dyn EventSink>,
+    sequencer: Arc<dyn Sequencer>,
     topic: String,
+    dead_letter_topic: String,
+    encoder: Encoder,
+    router: Router,
 ) -> impl Stream<Item = ()>
 where
     S: Stream<Item = MempoolUpdate>,
 {
-    let topic = Arc::new(tokio::sync::Mutex::new(topic));
-    let producer = Arc::new(std::sync::Mutex::new(producer));
     upstream.then(move |event| {
         let topic = topic.clone();
-        let producer = producer.clone();
+        let dead_letter_topic = dead_letter_topic.clone();
+        let sink = sink.clone();
+        let sequencer = sequencer.clone();
+        let router = router.clone();
         async move {
-            if let Ok(mempool_event) = MempoolEvent::try_from(event.clone()) {
-                let kafka_string = serde_json::to_string(&mempool_event).unwrap();
-                let tx_id: String = event.tx_id().to_string();
-
-                let topic = topic.clone().lock().await.clone();
-                spawn_blocking(move || {
-                    let rec: &Record<String, String> =
-                        &Record::from_key_value(topic.as_str(), tx_id.clone(), kafka_string);
+            let tx_id: String = event.tx_id().to_string();
+            let event_type = match event {
+                MempoolUpdate::TxAccepted(_) => "TxAccepted",
+                MempoolUpdate::TxWithdrawn(_) | MempoolUpdate::TxConfirmed(_) => "TxWithdrawn",
+            };
+            let tx = match &event {
+                MempoolUpdate::TxAccepted(tx)
+                | MempoolUpdate::TxWithdrawn(tx)
+                | MempoolUpdate::TxConfirmed(tx) => tx,
+            };
+            let mut records = match encoder.mempool_update(event.clone()) {
+                Ok(value) => {
                     info!("Got new mempool event. Key: ${:?}", tx_id);
-                    producer.lock().unwrap().send(rec).unwrap();
-                    info!("New mempool event processed by kafka. Key: ${:?}", tx_id);
-                })
-                .await;
-            }
+                    let routed_topics = router.mempool_topics(tx);
+                    iter::once(topic)
+                        .chain(routed_topics)
+                        .map(|topic| {
+                            SinkRecord::new(topic, tx_id.clone(), value.clone())
+                                .with_header(EVENT_TYPE_HEADER, event_type)
+                        })
+                        .collect()
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to encode mempool tx {}: {}. Moving it to [{}]",
+                        tx_id, e, dead_letter_topic
+                    );
+                    vec![DeadLetter::new(topic, event_type, e, tx)
+                        .into_record(&dead_letter_topic, tx_id.clone())]
+                }
+            };
+            sequencer.stamp(&mut records).await;
+            send_batch_until_acked(&*sink, records).await;
+            info!("New mempool event processed by sink. Key: ${:?}", tx_id);
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ergo_chain_sync::cache::chain_cache::{ChainCache, InMemoryCache};
+    use ergo_chain_sync::model::Block;
+    use ergo_chain_sync::ChainUpgrade;
+    use ergo_lib::ergo_chain_types::{BlockId, Digest32};
+    use futures::{stream, StreamExt};
+
+    use crate::event_source::{block_event_source, finality_event_source, reorg_event_source};
+    use crate::finality::Finality;
+    use crate::models::address::Network;
+    use crate::models::block_event::{BlockEvent, Reorg};
+    use crate::models::encoding::{Encoder, Encoding, TxEncoding};
+    use crate::sink::memory::InMemorySink;
+    use crate::sink::sequencer::InMemorySequencer;
+    use crate::sink::{
+        EVENT_TYPE_HEADER, HEIGHT_HEADER, SCHEMA_VERSION, SCHEMA_VERSION_HEADER, SEQ_HEADER,
+    };
+
+    #[tokio::test]
+    async fn block_events_are_published_in_order() {
+        let blk = Block {
+            id: BlockId(Digest32::zero()),
+            parent_id: BlockId(Digest32::zero()),
+            height: 1,
+            timestamp: 0,
+            transactions: vec![],
+            header: None,
+            size: None,
+        };
+        let upstream = stream::iter(vec![
+            ChainUpgrade::RollForward(blk.clone()),
+            ChainUpgrade::RollBackward(blk),
+        ]);
+        let sink = InMemorySink::new();
+        let upgrades: Vec<_> = block_event_source(
+            upstream,
+            Arc::new(sink.clone()),
+            Arc::new(InMemorySequencer::new()),
+            "blocks".to_string(),
+            Encoder {
+                encoding: Encoding::Json,
+                tx_encoding: TxEncoding::CborBase64,
+                include_spending_proofs: false,
+                network: Network::Mainnet,
+            },
+        )
+        .collect()
+        .await;
+
+        let records = sink.records();
+        assert_eq!(upgrades.len(), 2);
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r.topic == "blocks"));
+        assert!(String::from_utf8_lossy(records[0].value.as_ref().unwrap()).contains("BlockApply"));
+        assert!(
+            String::from_utf8_lossy(records[1].value.as_ref().unwrap()).contains("BlockUnapply")
+        );
+        assert_eq!(records[0].header(EVENT_TYPE_HEADER), Some("BlockApply"));
+        assert_eq!(records[1].header(EVENT_TYPE_HEADER), Some("BlockUnapply"));
+        assert_eq!(records[0].header(SEQ_HEADER), Some("0"));
+        assert_eq!(records[1].header(SEQ_HEADER), Some("1"));
+        let schema_version = SCHEMA_VERSION.to_string();
+        assert!(records
+            .iter()
+            .all(|r| r.header(SCHEMA_VERSION_HEADER) == Some(schema_version.as_str())));
+    }
+
+    fn block(height: u32, fork: u8, parent_id: BlockId) -> Block {
+        Block {
+            id: BlockId(Digest32::from([height as u8 + fork; 32])),
+            parent_id,
+            height,
+            timestamp: 0,
+            transactions: vec![],
+            header: None,
+            size: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn confirmed_only_mode_passes_final_blocks_only() {
+        let mut cache = InMemoryCache::new();
+        let mut chain = vec![block(1, 0, BlockId(Digest32::zero()))];
+        for height in 2..=4 {
+            let blk = block(height, 0, chain.last().unwrap().id);
+            chain.push(blk);
+        }
+        for blk in &chain {
+            cache.append_block(blk.clone()).await;
+        }
+        // Block 4 is replaced by a fork, which is shallower than the finality depth.
+        let fork = block(4, 100, chain[2].id);
+        let upstream = stream::iter(vec![
+            vec![
+                ChainUpgrade::RollForward(chain[1].clone()),
+                ChainUpgrade::RollForward(chain[2].clone()),
+            ],
+            vec![ChainUpgrade::RollForward(chain[3].clone())],
+            vec![
+                ChainUpgrade::RollBackward(chain[3].clone()),
+                ChainUpgrade::RollForward(fork),
+            ],
+        ]);
+        let sink = InMemorySink::new();
+        let upgrades: Vec<_> = finality_event_source(
+            upstream,
+            Arc::new(sink.clone()),
+            Arc::new(InMemorySequencer::new()),
+            "blocks".to_string(),
+            Some(Finality::new(cache, 2, true, Some(0))),
+            Encoder {
+                encoding: Encoding::Json,
+                tx_encoding: TxEncoding::CborBase64,
+                include_spending_proofs: false,
+                network: Network::Mainnet,
+            },
+        )
+        .collect()
+        .await;
+
+        let heights: Vec<_> = upgrades
+            .iter()
+            .flatten()
+            .map(|u| match u {
+                ChainUpgrade::RollForward(blk) => blk.height,
+                ChainUpgrade::RollBackward(_) => panic!("Rollback of a non-final block passed on"),
+            })
+            .collect();
+        assert_eq!(heights, vec![1, 2]);
+        let records = sink.records();
+        assert_eq!(records.len(), 2);
+        assert!(records
+            .iter()
+            .all(|r| r.header(EVENT_TYPE_HEADER) == Some("BlockFinalized")));
+        assert_eq!(records[0].header(HEIGHT_HEADER), Some("1"));
+        assert_eq!(records[1].header(HEIGHT_HEADER), Some("2"));
+    }
+
+    #[tokio::test]
+    async fn reorgs_are_enclosed_in_reorg_events() {
+        let b1 = block(1, 0, BlockId(Digest32::zero()));
+        let b2 = block(2, 0, b1.id);
+        let b3 = block(3, 0, b2.id);
+        let b2_fork = block(2, 100, b1.id);
+        let b3_fork = block(3, 100, b2_fork.id);
+        let b4_fork = block(4, 100, b3_fork.id);
+        let upstream = stream::iter(vec![
+            vec![ChainUpgrade::RollForward(b3.clone())],
+            vec![
+                ChainUpgrade::RollBackward(b3.clone()),
+                ChainUpgrade::RollBackward(b2.clone()),
+                ChainUpgrade::RollForward(b2_fork.clone()),
+                ChainUpgrade::RollForward(b3_fork.clone()),
+            ],
+            vec![ChainUpgrade::RollForward(b4_fork)],
+        ]);
+        let sink = InMemorySink::new();
+        let upgrades: Vec<_> = reorg_event_source(
+            upstream,
+            Arc::new(sink.clone()),
+            Arc::new(InMemorySequencer::new()),
+            Some("blocks".to_string()),
+            Encoder {
+                encoding: Encoding::Json,
+                tx_encoding: TxEncoding::CborBase64,
+                include_spending_proofs: false,
+                network: Network::Mainnet,
+            },
+        )
+        .collect()
+        .await;
+
+        assert_eq!(upgrades.len(), 6);
+        let records = sink.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].header(EVENT_TYPE_HEADER), Some("ReorgDetected"));
+        assert_eq!(records[1].header(EVENT_TYPE_HEADER), Some("ReorgCompleted"));
+        assert_eq!(records[0].header(HEIGHT_HEADER), Some("1"));
+        let hex = |blk: &Block| base16::encode_lower(blk.id.0 .0.as_ref());
+        let expected = Reorg {
+            common_ancestor_id: hex(&b1),
+            common_ancestor_height: 1,
+            orphaned_block_ids: vec![hex(&b3), hex(&b2)],
+            replacing_block_ids: vec![hex(&b2_fork), hex(&b3_fork)],
+        };
+        assert_eq!(
+            serde_json::from_slice::<BlockEvent>(records[0].value.as_ref().unwrap()).unwrap(),
+            BlockEvent::ReorgDetected(expected.clone())
+        );
+        assert_eq!(
+            serde_json::from_slice::<BlockEvent>(records[1].value.as_ref().unwrap()).unwrap(),
+            BlockEvent::ReorgCompleted(expected)
+        );
+    }
+}
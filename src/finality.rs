@@ -0,0 +1,162 @@
+use ergo_chain_sync::cache::chain_cache::ChainCache;
+use ergo_chain_sync::model::Block;
+use ergo_chain_sync::ChainUpgrade;
+use ergo_lib::ergo_chain_types::BlockId;
+use log::warn;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The cache doesn't reach as deep as the finality depth, or was rolled back below it.
+    #[error("block {0} is missing from the chain cache")]
+    MissingBlock(BlockId),
+}
+
+/// Tracks blocks becoming final, i.e. getting `depth` blocks on top of them, by walking the
+/// chain cached by chain sync back from the tip.
+pub struct Finality<C> {
+    cache: C,
+    depth: u32,
+    confirmed_only: bool,
+    /// Height of the most recent final block, if any.
+    final_height: Option<u32>,
+}
+
+impl<C: ChainCache> Finality<C> {
+    pub fn new(cache: C, depth: u32, confirmed_only: bool, final_height: Option<u32>) -> Self {
+        Self {
+            cache,
+            depth,
+            confirmed_only,
+            final_height,
+        }
+    }
+
+    /// Blocks made final by the given upgrade, oldest first, along with the upgrades to pass
+    /// downstream. In confirmed-only mode, those are roll forwards of the final blocks, and
+    /// roll backwards of blocks which were final already.
+    pub async fn apply(
+        &mut self,
+        upgrade: ChainUpgrade,
+    ) -> Result<(Vec<Block>, Vec<ChainUpgrade>), Error> {
+        match upgrade {
+            ChainUpgrade::RollForward(blk) => {
+                let finalized = self.finalized_by(&blk).await?;
+                if let Some(last) = finalized.last() {
+                    self.final_height = Some(last.height);
+                }
+                let upgrades = if self.confirmed_only {
+                    finalized
+                        .iter()
+                        .cloned()
+                        .map(ChainUpgrade::RollForward)
+                        .collect()
+                } else {
+                    vec![ChainUpgrade::RollForward(blk)]
+                };
+                Ok((finalized, upgrades))
+            }
+            ChainUpgrade::RollBackward(blk) => {
+                let was_final = self.final_height.map_or(false, |h| blk.height <= h);
+                if was_final {
+                    warn!(
+                        "Final block {} at height {} is rolled back, the reorg is deeper than {} blocks",
+                        blk.id, blk.height, self.depth
+                    );
+                    self.final_height = Some(blk.height.saturating_sub(1));
+                }
+                let upgrades = if was_final || !self.confirmed_only {
+                    vec![ChainUpgrade::RollBackward(blk)]
+                } else {
+                    vec![]
+                };
+                Ok((vec![], upgrades))
+            }
+        }
+    }
+
+    /// Blocks which weren't final before the given one was applied, but are now.
+    async fn finalized_by(&mut self, blk: &Block) -> Result<Vec<Block>, Error> {
+        let target = match blk.height.checked_sub(self.depth) {
+            Some(target) => target,
+            None => return Ok(vec![]),
+        };
+        let lowest = self.final_height.map_or(target, |h| h + 1);
+        if lowest > target {
+            return Ok(vec![]);
+        }
+        // The walk starts at the parent, since the block itself may be rolled back in the cache
+        // already, which runs ahead of the stream.
+        let mut ids = Vec::new();
+        let mut id = blk.parent_id;
+        let mut height = blk.height - 1;
+        while height >= lowest {
+            if height <= target {
+                ids.push(id);
+            }
+            if height == lowest {
+                break;
+            }
+            id = self
+                .cache
+                .get_parent_id(id)
+                .await
+                .ok_or(Error::MissingBlock(id))?;
+            height -= 1;
+        }
+        let mut finalized = Vec::with_capacity(ids.len());
+        for id in ids.into_iter().rev() {
+            let final_blk = self
+                .cache
+                .get_block(id)
+                .await
+                .ok_or(Error::MissingBlock(id))?;
+            finalized.push(final_blk);
+        }
+        Ok(finalized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_chain_sync::cache::chain_cache::{ChainCache, InMemoryCache};
+    use ergo_chain_sync::model::Block;
+    use ergo_chain_sync::ChainUpgrade;
+    use ergo_lib::ergo_chain_types::{BlockId, Digest32};
+
+    use crate::finality::{Error, Finality};
+
+    fn block(height: u32, parent_id: BlockId) -> Block {
+        Block {
+            id: BlockId(Digest32::from([height as u8; 32])),
+            parent_id,
+            height,
+            timestamp: 0,
+            transactions: vec![],
+            header: None,
+            size: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn blocks_missing_from_the_cache_fail_finalization() {
+        let b1 = block(1, BlockId(Digest32::zero()));
+        let b2 = block(2, b1.id);
+        let b3 = block(3, b2.id);
+        let b4 = block(4, b3.id);
+        let b5 = block(5, b4.id);
+        let mut cache = InMemoryCache::new();
+        // Block 3 is missing, so the walk from block 4 down to block 2 breaks off.
+        for blk in [&b1, &b2, &b4] {
+            cache.append_block(blk.clone()).await;
+        }
+        let mut finality = Finality::new(cache, 2, false, Some(1));
+        match finality.apply(ChainUpgrade::RollForward(b5)).await {
+            Err(Error::MissingBlock(id)) => assert_eq!(id, b3.id),
+            other => panic!(
+                "Expected a missing block, got {:?}",
+                other.map(|(blks, _)| blks)
+            ),
+        }
+    }
+}
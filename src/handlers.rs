@@ -1 +1,4 @@
+pub mod address_deltas;
+pub mod boxes;
 pub mod proxy;
+pub mod tokens;
@@ -0,0 +1,47 @@
+use async_trait::async_trait;
+use spectrum_offchain::event_sink::types::EventHandler;
+
+use crate::models::address::Network;
+use crate::models::address_delta::AddressDeltaEvent;
+use crate::models::encoding::Encoder;
+use crate::models::tx_event::TxEvent;
+use crate::sink::{RecordBatch, SinkRecord, BLOCK_ID_HEADER, EVENT_TYPE_HEADER, HEIGHT_HEADER};
+
+/// Publishes net changes of the holdings of every address a tx touches, keyed by address.
+pub struct AddressDeltas {
+    pub batch: RecordBatch,
+    pub topic: String,
+    pub network: Network,
+    pub encoder: Encoder,
+}
+
+impl AddressDeltas {
+    pub fn new(batch: RecordBatch, topic: String, network: Network, encoder: Encoder) -> Self {
+        Self {
+            batch,
+            topic,
+            network,
+            encoder,
+        }
+    }
+}
+
+#[async_trait(? Send)]
+impl EventHandler<TxEvent> for AddressDeltas {
+    async fn try_handle(&mut self, ev: TxEvent) -> Option<TxEvent> {
+        for delta_ev in AddressDeltaEvent::from_tx_event(&ev, self.network) {
+            let address = delta_ev.delta().address.clone();
+            let event_type = delta_ev.event_type();
+            let height = delta_ev.delta().context.height;
+            let block_id = delta_ev.delta().context.block_id.clone();
+            let value = self.encoder.address_delta_event(delta_ev);
+            self.batch.push(
+                SinkRecord::new(&self.topic, address, value)
+                    .with_header(EVENT_TYPE_HEADER, event_type)
+                    .with_header(HEIGHT_HEADER, height)
+                    .with_header(BLOCK_ID_HEADER, block_id),
+            );
+        }
+        Some(ev)
+    }
+}
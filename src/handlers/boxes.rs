@@ -0,0 +1,155 @@
+use async_trait::async_trait;
+use log::warn;
+use spectrum_offchain::event_sink::types::EventHandler;
+
+use crate::models::box_event::BoxEvent;
+use crate::models::dead_letter::DeadLetter;
+use crate::models::encoding::Encoder;
+use crate::models::tx_event::TxEvent;
+use crate::sink::{RecordBatch, SinkRecord, BLOCK_ID_HEADER, EVENT_TYPE_HEADER, HEIGHT_HEADER};
+
+/// Publishes every box created or spent by a tx, keyed by box ID.
+pub struct BoxEvents {
+    pub batch: RecordBatch,
+    pub topic: String,
+    pub dead_letter_topic: String,
+    /// Whether events removing a box from the UTXO set are published as tombstones, so that
+    /// a compacted topic retains unspent boxes only.
+    pub tombstones: bool,
+    pub encoder: Encoder,
+}
+
+impl BoxEvents {
+    pub fn new(
+        batch: RecordBatch,
+        topic: String,
+        dead_letter_topic: String,
+        tombstones: bool,
+        encoder: Encoder,
+    ) -> Self {
+        Self {
+            batch,
+            topic,
+            dead_letter_topic,
+            tombstones,
+            encoder,
+        }
+    }
+}
+
+#[async_trait(? Send)]
+impl EventHandler<TxEvent> for BoxEvents {
+    async fn try_handle(&mut self, ev: TxEvent) -> Option<TxEvent> {
+        for box_ev in BoxEvent::from_tx_event(ev.clone()) {
+            let box_id: String = box_ev.ergo_box().box_id().into();
+            let event_type = box_ev.event_type();
+            let height = box_ev.height();
+            let block_id = box_ev.block_id().to_string();
+            let record = if self.tombstones && box_ev.removes_box() {
+                SinkRecord::tombstone(&self.topic, box_id)
+            } else {
+                match self.encoder.box_event(box_ev) {
+                    Ok(value) => SinkRecord::new(&self.topic, box_id, value),
+                    Err(e) => {
+                        warn!(
+                            "Failed to encode box {} of block {}: {}. Moving it to [{}]",
+                            box_id, block_id, e, self.dead_letter_topic
+                        );
+                        let letter = DeadLetter::new(&self.topic, event_type, e, ev.tx())
+                            .in_block(&block_id, height);
+                        self.batch
+                            .push(letter.into_record(&self.dead_letter_topic, box_id));
+                        continue;
+                    }
+                }
+            };
+            self.batch.push(
+                record
+                    .with_header(EVENT_TYPE_HEADER, event_type)
+                    .with_header(HEIGHT_HEADER, height)
+                    .with_header(BLOCK_ID_HEADER, &block_id),
+            );
+        }
+        Some(ev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use spectrum_offchain::event_sink::types::EventHandler;
+
+    use crate::handlers::boxes::BoxEvents;
+    use crate::models::address::Network;
+    use crate::models::encoding::{Encoder, Encoding, TxEncoding};
+    use crate::models::fixtures::{applied, ergo_box, stamp, tx, unapplied};
+    use crate::models::tx_event::TxEvent;
+    use crate::sink::memory::InMemorySink;
+    use crate::sink::sequencer::InMemorySequencer;
+    use crate::sink::{RecordBatch, SinkRecord, EVENT_TYPE_HEADER};
+
+    async fn publish(ev: TxEvent, tombstones: bool) -> Vec<SinkRecord> {
+        let sink = InMemorySink::new();
+        let batch = RecordBatch::new(Arc::new(sink.clone()), Arc::new(InMemorySequencer::new()));
+        let mut handler = BoxEvents::new(
+            batch.clone(),
+            "boxes".to_string(),
+            "dead_letters".to_string(),
+            tombstones,
+            Encoder {
+                encoding: Encoding::Json,
+                tx_encoding: TxEncoding::CborBase64,
+                include_spending_proofs: false,
+                network: Network::Mainnet,
+            },
+        );
+        assert!(handler.try_handle(ev).await.is_some());
+        batch.flush().await;
+        sink.records()
+    }
+
+    /// Key, event type and whether a value is present, for every record.
+    fn summary(records: &[SinkRecord]) -> Vec<(&str, Option<&str>, bool)> {
+        records
+            .iter()
+            .map(|r| {
+                (
+                    r.key.as_str(),
+                    r.header(EVENT_TYPE_HEADER),
+                    r.value.is_some(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn removed_boxes_are_published_as_tombstones_keyed_by_box_id() {
+        let spent = stamp(ergo_box(0, 2_000_000, &[]), 1, 0);
+        let transfer = tx(2, vec![spent.clone()], vec![ergo_box(1, 2_000_000, &[])]);
+        let created: String = transfer.outputs.first().box_id().into();
+        let spent: String = spent.box_id().into();
+
+        let records = publish(applied(transfer.clone()), true).await;
+        assert_eq!(
+            summary(&records),
+            vec![
+                (spent.as_str(), Some("BoxSpent"), false),
+                (created.as_str(), Some("BoxCreated"), true),
+            ]
+        );
+
+        // Rolling back removes the created box and restores the spent one.
+        let records = publish(unapplied(transfer.clone()), true).await;
+        assert_eq!(
+            summary(&records),
+            vec![
+                (created.as_str(), Some("BoxUncreated"), false),
+                (spent.as_str(), Some("BoxUnspent"), true),
+            ]
+        );
+
+        let records = publish(applied(transfer), false).await;
+        assert!(records.iter().all(|r| r.value.is_some()));
+    }
+}
@@ -1,93 +1,98 @@
-use async_std::task::spawn_blocking;
-use base64::{engine::general_purpose, Engine as _};
-use spectrum_offchain::event_sink::types::EventHandler;
-use std::sync::Arc;
-
-use kafka::producer::{Producer, Record};
+use std::iter;
 
 use async_trait::async_trait;
-use log::info;
-use serde_json::json;
+use log::{info, warn};
+use spectrum_offchain::event_sink::types::EventHandler;
 
-use crate::models::cbor::CborBlockTransaction;
-use crate::models::tx_event::TxEvent;
+use crate::models::address::Network;
+use crate::models::dead_letter::DeadLetter;
+use crate::models::encoding::Encoder;
+use crate::models::tx_event::{TxEvent, TxKeying};
+use crate::routing::Router;
+use crate::sink::{RecordBatch, SinkRecord, BLOCK_ID_HEADER, EVENT_TYPE_HEADER, HEIGHT_HEADER};
 
 pub struct ProxyEvents {
-    pub producer: Arc<std::sync::Mutex<Producer>>,
+    pub batch: RecordBatch,
     pub topic: String,
+    pub dead_letter_topic: String,
+    pub keying: TxKeying,
+    pub network: Network,
+    pub encoder: Encoder,
+    pub router: Router,
 }
 
 impl ProxyEvents {
-    pub fn new(producer: Arc<std::sync::Mutex<Producer>>, topic: String) -> Self {
-        Self { producer, topic }
+    pub fn new(
+        batch: RecordBatch,
+        topic: String,
+        dead_letter_topic: String,
+        keying: TxKeying,
+        network: Network,
+        encoder: Encoder,
+        router: Router,
+    ) -> Self {
+        Self {
+            batch,
+            topic,
+            dead_letter_topic,
+            keying,
+            network,
+            encoder,
+            router,
+        }
     }
 }
 
 #[async_trait(? Send)]
 impl EventHandler<TxEvent> for ProxyEvents {
     async fn try_handle(&mut self, ev: TxEvent) -> Option<TxEvent> {
-        let topic = Arc::new(tokio::sync::Mutex::new(self.topic.clone()));
-        let producer = self.producer.clone();
+        let topic = self.topic.clone();
+        let dead_letter_topic = self.dead_letter_topic.clone();
+        let batch = self.batch.clone();
+        let keys = ev.keys(self.keying, self.network);
+        let encoder = self.encoder;
+        let routed_topics = self.router.tx_topics(ev.tx());
 
         let ev_clone = ev.clone();
         async move {
-            let tx_id: String = match &ev_clone {
-                TxEvent::AppliedTx { tx, .. } | TxEvent::UnappliedTx { tx, .. } => tx.id.into(),
-            };
-
-            let kafka_json = match ev_clone.clone() {
+            let tx_id: String = ev.tx().id.into();
+            let (event_type, block_height, block_id) = match &ev {
                 TxEvent::AppliedTx {
-                    timestamp,
-                    tx,
                     block_height,
                     block_id,
-                } => {
-                    let cbor_tx = CborBlockTransaction::from(tx);
-                    let tx_bytes = serde_cbor::to_vec(&cbor_tx).unwrap();
-                    let tx_base64 = general_purpose::STANDARD.encode(tx_bytes);
-                    json!({
-                        "AppliedEvent": {
-                            "timestamp": timestamp,
-                            "height": block_height,
-                            "tx": tx_base64,
-                            "block_id": block_id,
-                        }
-                    })
-                }
+                    ..
+                } => ("AppliedTx", *block_height, block_id.clone()),
                 TxEvent::UnappliedTx {
-                    timestamp,
-                    tx,
                     block_height,
                     block_id,
-                } => {
-                    let cbor_tx = CborBlockTransaction::from(tx);
-                    let tx_bytes = serde_cbor::to_vec(&cbor_tx).unwrap();
-                    let tx_base64 = general_purpose::STANDARD.encode(tx_bytes);
-                    json!({
-                        "UnappliedEvent": {
-                            "timestamp": timestamp,
-                            "height": block_height,
-                            "tx": tx_base64,
-                            "block_id": block_id,
-                        }
-                    })
-                }
+                    ..
+                } => ("UnappliedTx", *block_height, block_id.clone()),
             };
-            let kafka_string = kafka_json.to_string();
+            info!("Got new event. Type: {}, Key: ${:?}", event_type, tx_id);
 
-            let topic = topic.clone().lock().await.clone();
-            spawn_blocking(move || {
-                let rec: &Record<String, String> =
-                    &Record::from_key_value(topic.as_str(), tx_id.clone(), kafka_string);
-                let event_type = match ev_clone {
-                    TxEvent::AppliedTx { .. } => "AppliedTx",
-                    TxEvent::UnappliedTx { .. } => "UnappliedTx",
-                };
-                info!("Got new event. Type: {}, Key: ${:?}", event_type, tx_id);
-                producer.lock().unwrap().send(rec).unwrap();
-                info!("New event processed by kafka. Key: ${:?}", tx_id);
-            })
-            .await;
+            let value = match encoder.tx_event(ev_clone) {
+                Ok(value) => value,
+                Err(e) => {
+                    warn!(
+                        "Failed to encode tx {} of block {}: {}. Moving it to [{}]",
+                        tx_id, block_id, e, dead_letter_topic
+                    );
+                    let letter = DeadLetter::new(topic, event_type, e, ev.tx())
+                        .in_block(block_id, block_height);
+                    batch.push(letter.into_record(&dead_letter_topic, tx_id));
+                    return Some(ev);
+                }
+            };
+            for topic in iter::once(&topic).chain(&routed_topics) {
+                for key in &keys {
+                    batch.push(
+                        SinkRecord::new(topic, key, value.clone())
+                            .with_header(EVENT_TYPE_HEADER, event_type)
+                            .with_header(HEIGHT_HEADER, block_height)
+                            .with_header(BLOCK_ID_HEADER, &block_id),
+                    );
+                }
+            }
             Some(ev)
         }
         .await
@@ -0,0 +1,47 @@
+use async_trait::async_trait;
+use spectrum_offchain::event_sink::types::EventHandler;
+
+use crate::models::address::Network;
+use crate::models::encoding::Encoder;
+use crate::models::token_event::TokenEvent;
+use crate::models::tx_event::TxEvent;
+use crate::sink::{RecordBatch, SinkRecord, BLOCK_ID_HEADER, EVENT_TYPE_HEADER, HEIGHT_HEADER};
+
+/// Publishes mints, burns and transfers of tokens, keyed by token ID.
+pub struct TokenEvents {
+    pub batch: RecordBatch,
+    pub topic: String,
+    pub network: Network,
+    pub encoder: Encoder,
+}
+
+impl TokenEvents {
+    pub fn new(batch: RecordBatch, topic: String, network: Network, encoder: Encoder) -> Self {
+        Self {
+            batch,
+            topic,
+            network,
+            encoder,
+        }
+    }
+}
+
+#[async_trait(? Send)]
+impl EventHandler<TxEvent> for TokenEvents {
+    async fn try_handle(&mut self, ev: TxEvent) -> Option<TxEvent> {
+        for token_ev in TokenEvent::from_tx_event(&ev, self.network) {
+            let token_id = token_ev.token_id().to_string();
+            let event_type = token_ev.event_type();
+            let height = token_ev.context().height;
+            let block_id = token_ev.context().block_id.clone();
+            let value = self.encoder.token_event(token_ev);
+            self.batch.push(
+                SinkRecord::new(&self.topic, token_id, value)
+                    .with_header(EVENT_TYPE_HEADER, event_type)
+                    .with_header(HEIGHT_HEADER, height)
+                    .with_header(BLOCK_ID_HEADER, block_id),
+            );
+        }
+        Some(ev)
+    }
+}
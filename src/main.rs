@@ -1,29 +1,50 @@
 mod event_source;
+mod finality;
 mod handlers;
 mod models;
+mod reorg;
+mod routing;
+mod sink;
 
 use clap::{arg, Parser};
 use ergo_chain_sync::cache::rocksdb::ChainCacheRocksDB;
+use ergo_chain_sync::checkpoint::{Checkpoint, CheckpointRocksDB};
 use ergo_chain_sync::client::node::ErgoNodeHttpClient;
+use ergo_chain_sync::client::pool::{ErgoNodePool, NodePoolConf};
 use ergo_chain_sync::client::types::Url;
 use ergo_chain_sync::rocksdb::RocksConfig;
-use ergo_chain_sync::{chain_sync_stream, ChainSync, ChainSyncNonInit};
+use ergo_chain_sync::sizing::AdaptiveSizingConf;
+use ergo_chain_sync::{chain_sync_batch_stream, ChainSync, ChainSyncConf, ChainSyncNonInit};
 use ergo_mempool_sync::{mempool_sync_stream, MempoolSyncConf};
 use futures::Stream;
 use isahc::{prelude::*, HttpClient};
 use serde::Deserialize;
 use std::pin::Pin;
 use std::sync::{Arc, Once};
-use std::time::Duration;
+use tokio::sync::Mutex;
 
 use futures::StreamExt;
-use kafka::producer::{Producer, RequiredAcks};
 
+use crate::handlers::address_deltas::AddressDeltas;
+use crate::handlers::boxes::BoxEvents;
 use crate::handlers::proxy::ProxyEvents;
+use crate::handlers::tokens::TokenEvents;
 use spectrum_offchain::event_sink::types::{EventHandler, NoopDefaultHandler};
 
-use crate::event_source::{block_event_source, mempool_event_source, tx_event_source};
-use crate::models::tx_event::TxEvent;
+use crate::event_source::{
+    block_event_source, finality_event_source, header_event_source, mempool_event_source,
+    reorg_event_source, tx_event_source,
+};
+use crate::finality::Finality;
+use crate::models::address::Network;
+use crate::models::encoding::{Encoder, Encoding, TxEncoding};
+use crate::models::tx_event::{TxEvent, TxKeying};
+use crate::routing::{Route, Router};
+use crate::sink::kafka::topics::{provision_topics, Keying, TopicSpec, TopicsConfig};
+use crate::sink::kafka::KafkaConfig;
+use crate::sink::outbox::{drain_outbox, Outbox, OutboxConfig, OutboxSink};
+use crate::sink::sequencer::{Sequencer, SequencerRocksDB};
+use crate::sink::{make_sink, EventSink, RecordBatch, SinkConfig};
 use futures::stream::select_all;
 use spectrum_offchain::event_sink::process_events;
 
@@ -34,10 +55,14 @@ async fn main() {
         std::fs::read_to_string(args.config_yaml_path).expect("Cannot load configuration file");
     let config: AppConfig = serde_yaml::from_str(&raw_config).expect("Invalid configuration file");
 
-    if let Some(log4rs_path) = args.log4rs_path {
-        log4rs::init_file(log4rs_path, Default::default()).unwrap();
+    let log4rs_path = args
+        .log4rs_path
+        .as_deref()
+        .unwrap_or(config.log4rs_yaml_path);
+    if let SinkConfig::Stdout = config.sink {
+        init_logging_to_stderr(log4rs_path);
     } else {
-        log4rs::init_file(config.log4rs_yaml_path, Default::default()).unwrap();
+        log4rs::init_file(log4rs_path, Default::default()).unwrap();
     }
     let client = HttpClient::builder()
         .timeout(std::time::Duration::from_secs(
@@ -46,48 +71,169 @@ async fn main() {
         .build()
         .unwrap();
 
-    let node = ErgoNodeHttpClient::new(client, config.node_addr.clone());
+    let node = ErgoNodePool::new(
+        std::iter::once(&config.node_addr)
+            .chain(&config.fallback_node_addrs)
+            .map(|addr| ErgoNodeHttpClient::new(client.clone(), addr.clone()))
+            .collect(),
+        NodePoolConf {
+            max_lag: config.node_max_lag.unwrap_or(2),
+        },
+    );
     let cache = ChainCacheRocksDB::new(RocksConfig {
         db_path: config.chain_cache_db_path.into(),
     });
+    let mut checkpoint = CheckpointRocksDB::new(cache.db.clone());
+    let finality_cache = cache.clone();
+    let max_finality_depth = cache.max_rollback_depth;
+    let sequencer: Arc<dyn Sequencer> = Arc::new(SequencerRocksDB::new(cache.db.clone()));
+    let chain_sync_conf = ChainSyncConf {
+        batch_size: config.chain_sync_batch_size,
+        chunk_size: config.chain_sync_chunk_size,
+        adaptive_sizing: config.chain_sync_adaptive_sizing,
+        prefetch: config.chain_sync_prefetch.unwrap_or(1),
+        throttle_ms: config.chain_sync_throttle_ms,
+    };
     static SIGNAL_TIP_REACHED: Once = Once::new();
     let chain_sync = ChainSync::init(
         config.chain_sync_starting_height,
         &node,
         cache,
         Some(&SIGNAL_TIP_REACHED),
-        config.chain_sync_batch_size,
-        config.chain_sync_chunk_size,
-        config.chain_sync_throttle_ms,
+        chain_sync_conf,
     )
     .await;
+    let last_published = checkpoint.get().await;
+    if let Some(last_published) = last_published.clone() {
+        chain_sync
+            .resume_from(last_published)
+            .await
+            .expect("Cannot resume from checkpoint");
+    }
+    if config.confirmed_only && config.finality_depth.is_none() {
+        panic!("`finality_depth` is required along with `confirmed_only`");
+    }
+    let finality = config.finality_depth.map(|depth| {
+        // Final blocks are looked up in the chain cache, so they must not be evicted yet.
+        if depth == 0 || depth >= max_finality_depth {
+            panic!(
+                "`finality_depth` must be between 1 and {}",
+                max_finality_depth - 1
+            );
+        }
+        // In confirmed-only mode, the checkpoint is the last final block. Otherwise it is the
+        // tip, `depth` blocks ahead of the last final one.
+        let final_height = last_published.map(|tip| {
+            if config.confirmed_only {
+                tip.height
+            } else {
+                tip.height.saturating_sub(depth)
+            }
+        });
+        Finality::new(finality_cache, depth, config.confirmed_only, final_height)
+    });
     let cache_mempool = ChainCacheRocksDB::new(RocksConfig {
         db_path: config.mempool_cache_db_path.into(),
     });
 
-    let producer1 = Producer::from_hosts(vec![config.kafka_address.to_owned()])
-        .with_ack_timeout(Duration::from_secs(1))
-        .with_required_acks(RequiredAcks::One)
-        .create()
-        .unwrap();
-    let producer2 = Producer::from_hosts(vec![config.kafka_address.to_owned()])
-        .with_ack_timeout(Duration::from_secs(1))
-        .with_required_acks(RequiredAcks::One)
-        .create()
-        .unwrap();
-    let producer3 = Producer::from_hosts(vec![config.kafka_address.to_owned()])
-        .with_ack_timeout(Duration::from_secs(1))
-        .with_required_acks(RequiredAcks::One)
-        .create()
-        .unwrap();
+    let router = Router::new(config.routes.clone(), config.network);
 
-    let mempool_chain_sync = ChainSyncNonInit::new(
-        &node,
-        cache_mempool,
-        config.chain_sync_batch_size,
-        config.chain_sync_chunk_size,
-        config.chain_sync_throttle_ms,
-    );
+    if let SinkConfig::Kafka = config.sink {
+        let mut topics = vec![
+            TopicSpec {
+                name: config.blocks_topic.to_string(),
+                keying: Keying::Total,
+                settings: config.topics.blocks.clone(),
+            },
+            TopicSpec {
+                name: config.tx_topic.to_string(),
+                keying: config.tx_keying.into(),
+                settings: config.topics.tx.clone(),
+            },
+            TopicSpec {
+                name: config.mempool_topic.to_string(),
+                keying: Keying::PerKey,
+                settings: config.topics.mempool.clone(),
+            },
+            TopicSpec {
+                name: config.dead_letter_topic.to_string(),
+                keying: Keying::PerKey,
+                settings: config.topics.dead_letter.clone(),
+            },
+        ];
+        if let Some(headers_topic) = config.headers_topic {
+            topics.push(TopicSpec {
+                name: headers_topic.to_string(),
+                keying: Keying::Total,
+                settings: config
+                    .topics
+                    .headers
+                    .clone()
+                    .expect("`topics.headers` is required along with `headers_topic`"),
+            });
+        }
+        if let Some(boxes_topic) = config.boxes_topic {
+            topics.push(TopicSpec {
+                name: boxes_topic.to_string(),
+                keying: Keying::PerKey,
+                settings: config
+                    .topics
+                    .boxes
+                    .clone()
+                    .expect("`topics.boxes` is required along with `boxes_topic`"),
+            });
+        }
+        if let Some(tokens_topic) = config.tokens_topic {
+            topics.push(TopicSpec {
+                name: tokens_topic.to_string(),
+                keying: Keying::PerKey,
+                settings: config
+                    .topics
+                    .tokens
+                    .clone()
+                    .expect("`topics.tokens` is required along with `tokens_topic`"),
+            });
+        }
+        if let Some(address_deltas_topic) = config.address_deltas_topic {
+            topics.push(TopicSpec {
+                name: address_deltas_topic.to_string(),
+                keying: Keying::PerKey,
+                settings: config.topics.address_deltas.clone().expect(
+                    "`topics.address_deltas` is required along with `address_deltas_topic`",
+                ),
+            });
+        }
+        // Routed topics are keyed the same way as the topics they are derived from.
+        for name in router.topics(|route| route.topic.as_ref()) {
+            topics.push(TopicSpec {
+                name,
+                keying: Keying::PerKey,
+                settings: config.topics.tx.clone(),
+            });
+        }
+        for name in router.topics(|route| route.mempool_topic.as_ref()) {
+            topics.push(TopicSpec {
+                name,
+                keying: Keying::PerKey,
+                settings: config.topics.mempool.clone(),
+            });
+        }
+        if let Err(e) = provision_topics(&config.kafka, &config.topics, topics).await {
+            panic!("Topics are not set up properly: {}", e);
+        }
+    }
+    let encoder = Encoder {
+        encoding: config.encoding,
+        tx_encoding: config.tx_encoding,
+        include_spending_proofs: config.include_spending_proofs,
+        network: config.network,
+    };
+    let sink = make_sink(&config.sink, &config.kafka);
+    let outbox = Arc::new(Outbox::new(&config.outbox.db_path).expect("Cannot open outbox"));
+    let outbox_drain = drain_outbox(outbox.clone(), sink.clone(), config.outbox.clone());
+    let sink: Arc<dyn EventSink> = Arc::new(OutboxSink::new(sink, outbox));
+
+    let mempool_chain_sync = ChainSyncNonInit::new(&node, cache_mempool, chain_sync_conf);
 
     let mempool_sync = mempool_sync_stream(
         MempoolSyncConf {
@@ -98,46 +244,197 @@ async fn main() {
     )
     .await;
 
-    let mempool_source =
-        mempool_event_source(mempool_sync, producer3, config.mempool_topic.to_string());
-    let chain_upgrade_stream = chain_sync_stream(chain_sync);
-    let chain_upgrade_stream_with_blocks = block_event_source(
+    let mempool_source = mempool_event_source(
+        mempool_sync,
+        sink.clone(),
+        sequencer.clone(),
+        config.mempool_topic.to_string(),
+        config.dead_letter_topic.to_string(),
+        encoder,
+        router.clone(),
+    );
+    let chain_upgrade_stream = chain_sync_batch_stream(chain_sync);
+    let chain_upgrade_stream_with_finality = finality_event_source(
         chain_upgrade_stream,
-        producer1,
+        sink.clone(),
+        sequencer.clone(),
+        config.blocks_topic.to_string(),
+        finality,
+        encoder,
+    );
+    let chain_upgrade_stream_with_reorgs = reorg_event_source(
+        chain_upgrade_stream_with_finality,
+        sink.clone(),
+        sequencer.clone(),
+        config.reorg_events.then(|| config.blocks_topic.to_string()),
+        encoder,
+    );
+    let chain_upgrade_stream_with_blocks = block_event_source(
+        chain_upgrade_stream_with_reorgs,
+        sink.clone(),
+        sequencer.clone(),
         config.blocks_topic.to_string(),
+        encoder,
+    );
+    let chain_upgrade_stream_with_headers = header_event_source(
+        chain_upgrade_stream_with_blocks,
+        sink.clone(),
+        sequencer.clone(),
+        config.headers_topic.map(str::to_string),
+        &node,
+        config.include_block_extensions,
+        encoder,
+    );
+    let batch = RecordBatch::new(sink, sequencer);
+    let event_source = tx_event_source(
+        chain_upgrade_stream_with_headers,
+        batch.clone(),
+        Arc::new(Mutex::new(checkpoint)),
     );
-    let event_source = tx_event_source(chain_upgrade_stream_with_blocks);
     let handler = ProxyEvents::new(
-        Arc::new(std::sync::Mutex::new(producer2)),
+        batch.clone(),
         config.tx_topic.to_string(),
+        config.dead_letter_topic.to_string(),
+        config.tx_keying,
+        config.network,
+        encoder,
+        router,
     );
-    let handlers: Vec<Box<dyn EventHandler<TxEvent>>> = vec![Box::new(handler)];
+    let mut handlers: Vec<Box<dyn EventHandler<TxEvent>>> = vec![Box::new(handler)];
+    if let Some(boxes_topic) = config.boxes_topic {
+        handlers.push(Box::new(BoxEvents::new(
+            batch.clone(),
+            boxes_topic.to_string(),
+            config.dead_letter_topic.to_string(),
+            config.boxes_tombstones,
+            encoder,
+        )));
+    }
+    if let Some(tokens_topic) = config.tokens_topic {
+        handlers.push(Box::new(TokenEvents::new(
+            batch.clone(),
+            tokens_topic.to_string(),
+            config.network,
+            encoder,
+        )));
+    }
+    if let Some(address_deltas_topic) = config.address_deltas_topic {
+        handlers.push(Box::new(AddressDeltas::new(
+            batch,
+            address_deltas_topic.to_string(),
+            config.network,
+            encoder,
+        )));
+    }
 
     let default_handler = NoopDefaultHandler;
     let process_events_stream = boxed(process_events(event_source, handlers, default_handler));
 
-    let mut app = select_all(vec![process_events_stream, boxed(mempool_source)]);
+    let mut app = select_all(vec![
+        process_events_stream,
+        boxed(mempool_source),
+        boxed(outbox_drain),
+    ]);
 
     loop {
         app.select_next_some().await;
     }
 }
 
+/// Initializes logging from the given log4rs configuration with console appenders writing to
+/// stderr, so that log lines don't mix with the records the stdout sink writes.
+fn init_logging_to_stderr(log4rs_path: &str) {
+    let raw_config =
+        std::fs::read_to_string(log4rs_path).expect("Cannot load log4rs configuration file");
+    let mut log_config: serde_yaml::Value =
+        serde_yaml::from_str(&raw_config).expect("Invalid log4rs configuration file");
+    if let Some(appenders) = log_config
+        .get_mut("appenders")
+        .and_then(|appenders| appenders.as_mapping_mut())
+    {
+        for (_, appender) in appenders.iter_mut() {
+            if appender.get("kind").and_then(|kind| kind.as_str()) == Some("console") {
+                appender["target"] = serde_yaml::Value::from("stderr");
+            }
+        }
+    }
+    let log_config: log4rs::config::RawConfig =
+        serde_yaml::from_value(log_config).expect("Invalid log4rs configuration file");
+    log4rs::init_raw_config(log_config).unwrap();
+}
+
 #[derive(Deserialize)]
 struct AppConfig<'a> {
     node_addr: Url,
+    /// Further nodes of the same network, which requests fail over to.
+    #[serde(default)]
+    fallback_node_addrs: Vec<Url>,
+    /// Number of blocks a node may be behind the highest one before requests avoid it, 2 if unset.
+    #[serde(default)]
+    node_max_lag: Option<u32>,
+    #[serde(default)]
+    network: Network,
     http_client_timeout_duration_secs: u32,
     chain_sync_starting_height: u32,
     log4rs_yaml_path: &'a str,
     chain_cache_db_path: &'a str,
     mempool_cache_db_path: &'a str,
-    kafka_address: &'a str,
+    #[serde(default)]
+    sink: SinkConfig,
+    kafka: KafkaConfig,
+    topics: TopicsConfig,
+    outbox: OutboxConfig,
     blocks_topic: &'a str,
+    /// Number of blocks on top of a block for it to be considered final. No `BlockFinalized`
+    /// events are published if unset.
+    #[serde(default)]
+    finality_depth: Option<u32>,
+    /// Whether only final blocks and their txs are published to `blocks_topic` and `tx_topic`.
+    #[serde(default)]
+    confirmed_only: bool,
+    /// Whether reorgs are summarized by `ReorgDetected` and `ReorgCompleted` events.
+    #[serde(default)]
+    reorg_events: bool,
+    /// Topic of full block headers. Not published if unset.
+    #[serde(default, borrow)]
+    headers_topic: Option<&'a str>,
+    /// Whether header events carry the extension section of their block.
+    #[serde(default)]
+    include_block_extensions: bool,
     tx_topic: &'a str,
+    #[serde(default)]
+    tx_keying: TxKeying,
     mempool_topic: &'a str,
+    /// Topic of box events. Not published if unset.
+    #[serde(default, borrow)]
+    boxes_topic: Option<&'a str>,
+    #[serde(default)]
+    boxes_tombstones: bool,
+    /// Topic of token events. Not published if unset.
+    #[serde(default, borrow)]
+    tokens_topic: Option<&'a str>,
+    /// Topic of per-address balance deltas. Not published if unset.
+    #[serde(default, borrow)]
+    address_deltas_topic: Option<&'a str>,
+    dead_letter_topic: &'a str,
+    /// Rules republishing matching txs to dedicated topics.
+    #[serde(default)]
+    routes: Vec<Route>,
+    #[serde(default)]
+    encoding: Encoding,
+    #[serde(default)]
+    tx_encoding: TxEncoding,
+    #[serde(default)]
+    include_spending_proofs: bool,
     mempool_sync_interval_ms: u64,
     chain_sync_batch_size: u32,
     chain_sync_chunk_size: usize,
+    /// Max number of chunk requests in flight during sync, 1 if unset.
+    #[serde(default)]
+    chain_sync_prefetch: Option<usize>,
+    /// Bounds of batch and chunk sizes adjusted at runtime. Sizes are fixed if unset.
+    #[serde(default)]
+    chain_sync_adaptive_sizing: Option<AdaptiveSizingConf>,
     chain_sync_throttle_ms: u64,
 }
 
@@ -1,4 +1,15 @@
+pub mod address;
+pub mod address_delta;
 pub mod block_event;
+pub mod box_event;
 pub mod cbor;
+pub mod dead_letter;
+pub mod encoding;
+#[cfg(test)]
+pub mod fixtures;
+pub mod header_event;
 pub mod mempool_event;
+pub mod proto;
+pub mod registers;
+pub mod token_event;
 pub mod tx_event;
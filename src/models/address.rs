@@ -0,0 +1,55 @@
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Digest};
+use ergo_lib::ergotree_ir::chain::address::{Address, AddressEncoder, NetworkPrefix};
+use ergo_lib::ergotree_ir::ergo_tree::ErgoTree;
+use ergo_lib::ergotree_ir::serialization::SigmaSerializable;
+use serde::Deserialize;
+
+/// Ergo network the node belongs to. Determines the prefix of encoded addresses.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+}
+
+impl From<Network> for NetworkPrefix {
+    fn from(network: Network) -> Self {
+        match network {
+            Network::Mainnet => NetworkPrefix::Mainnet,
+            Network::Testnet => NetworkPrefix::Testnet,
+        }
+    }
+}
+
+/// Hex-encoded Blake2b256 hash of the serialized ErgoTree, if it can be serialized.
+pub fn ergo_tree_hash(tree: &ErgoTree) -> Option<String> {
+    let bytes = tree.sigma_serialize_bytes().ok()?;
+    Some(base16::encode_lower(
+        Blake2b::<U32>::digest(bytes).as_slice(),
+    ))
+}
+
+/// Hex-encoded Blake2b256 hash of the ErgoTree template, i.e. the tree without its segregated
+/// constants. Boxes guarded by the same contract share it regardless of contract parameters.
+pub fn ergo_tree_template_hash(tree: &ErgoTree) -> Option<String> {
+    let bytes = tree.template_bytes().ok()?;
+    Some(base16::encode_lower(
+        Blake2b::<U32>::digest(bytes).as_slice(),
+    ))
+}
+
+/// Base58-encoded address guarded by the given ErgoTree, if it can be recreated.
+pub fn encode_address(tree: &ErgoTree, network: Network) -> Option<String> {
+    Address::recreate_from_ergo_tree(tree)
+        .ok()
+        .map(|addr| AddressEncoder::encode_address_as_string(network.into(), &addr))
+}
+
+/// Base58-encoded address guarded by the given ErgoTree or, if the tree can't be parsed,
+/// its hex-encoded bytes. Lets boxes be grouped by holder regardless of their trees.
+pub fn encode_address_or_tree(tree: &ErgoTree, network: Network) -> String {
+    encode_address(tree, network)
+        .unwrap_or_else(|| base16::encode_lower(&tree.sigma_serialize_bytes().unwrap_or_default()))
+}
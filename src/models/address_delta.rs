@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::models::address::{encode_address_or_tree, Network};
+use crate::models::tx_event::{TxContext, TxEvent};
+
+/// Net change of the holdings of an address caused by a tx.
+#[derive(Debug, Clone)]
+pub enum AddressDeltaEvent {
+    AddressDeltaApplied(AddressDelta),
+    /// Emitted when the tx is rolled back, carrying the negated deltas, so that consumers can
+    /// keep running balances by adding up the deltas of all events.
+    AddressDeltaUnapplied(AddressDelta),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressDelta {
+    pub address: String,
+    /// Net change of nanoERG held by the address.
+    pub value: i64,
+    /// Net changes of token amounts held by the address, by token ID. Zero ones are omitted.
+    pub tokens: BTreeMap<String, i64>,
+    #[serde(flatten)]
+    pub context: TxContext,
+}
+
+impl AddressDeltaEvent {
+    /// Deltas of every address whose holdings the given tx event changes.
+    pub fn from_tx_event(ev: &TxEvent, network: Network) -> Vec<AddressDeltaEvent> {
+        let context = ev.context();
+        let tx = ev.tx();
+        let mut deltas: BTreeMap<String, (i64, BTreeMap<String, i64>)> = BTreeMap::new();
+        for (b, sign) in tx
+            .inputs
+            .iter()
+            .map(|input| (&input.ergo_box, -1))
+            .chain(tx.outputs.iter().map(|b| (b, 1)))
+        {
+            let (value, token_deltas) = deltas
+                .entry(encode_address_or_tree(&b.ergo_tree, network))
+                .or_default();
+            *value += sign * *b.value.as_u64() as i64;
+            for token in b.tokens.iter().flat_map(|tokens| tokens.iter()) {
+                *token_deltas.entry(token.token_id.into()).or_default() +=
+                    sign * *token.amount.as_u64() as i64;
+            }
+        }
+        let deltas = deltas
+            .into_iter()
+            .filter_map(|(address, (value, mut tokens))| {
+                tokens.retain(|_, amount| *amount != 0);
+                if value == 0 && tokens.is_empty() {
+                    return None;
+                }
+                Some(AddressDelta {
+                    address,
+                    value,
+                    tokens,
+                    context: context.clone(),
+                })
+            });
+        match ev {
+            TxEvent::AppliedTx { .. } => {
+                deltas.map(AddressDeltaEvent::AddressDeltaApplied).collect()
+            }
+            TxEvent::UnappliedTx { .. } => {
+                let mut events: Vec<_> = deltas
+                    .map(|delta| AddressDeltaEvent::AddressDeltaUnapplied(delta.negated()))
+                    .collect();
+                events.reverse();
+                events
+            }
+        }
+    }
+
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            AddressDeltaEvent::AddressDeltaApplied(_) => "AddressDeltaApplied",
+            AddressDeltaEvent::AddressDeltaUnapplied(_) => "AddressDeltaUnapplied",
+        }
+    }
+
+    pub fn delta(&self) -> &AddressDelta {
+        match self {
+            AddressDeltaEvent::AddressDeltaApplied(d)
+            | AddressDeltaEvent::AddressDeltaUnapplied(d) => d,
+        }
+    }
+
+    /// The event as JSON, with the payload under the event type.
+    pub fn to_json(&self) -> Value {
+        // Can't fail, since the payload consists of plain strings and numbers only.
+        let payload = serde_json::to_value(self.delta()).unwrap();
+        let mut json = serde_json::Map::new();
+        json.insert(self.event_type().to_string(), payload);
+        Value::Object(json)
+    }
+}
+
+impl AddressDelta {
+    fn negated(self) -> Self {
+        Self {
+            value: -self.value,
+            tokens: self
+                .tokens
+                .into_iter()
+                .map(|(token_id, amount)| (token_id, -amount))
+                .collect(),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ergo_chain_sync::client::model::BlockTransaction;
+
+    use crate::models::address::{encode_address_or_tree, Network};
+    use crate::models::address_delta::AddressDeltaEvent;
+    use crate::models::fixtures::{applied, ergo_box, p2pk_tree, stamp, token_id, tx, unapplied};
+    use crate::models::tx_event::TxEvent;
+
+    fn address(owner: usize) -> String {
+        encode_address_or_tree(&p2pk_tree(owner), Network::Mainnet)
+    }
+
+    /// Event type, address, value delta and token deltas of every event.
+    fn summary(ev: &TxEvent) -> Vec<(&'static str, String, i64, BTreeMap<String, i64>)> {
+        AddressDeltaEvent::from_tx_event(ev, Network::Mainnet)
+            .into_iter()
+            .map(|ev| {
+                let delta = ev.delta().clone();
+                (ev.event_type(), delta.address, delta.value, delta.tokens)
+            })
+            .collect()
+    }
+
+    /// Owner 0 sends ERG and some of a token to owner 1, keeping the rest along with another
+    /// token, while owner 2 moves its holdings to a new box.
+    fn payment_tx() -> BlockTransaction {
+        tx(
+            2,
+            vec![
+                stamp(
+                    ergo_box(0, 3_000_000, &[(token_id(7), 100), (token_id(8), 5)]),
+                    1,
+                    0,
+                ),
+                stamp(ergo_box(2, 1_000_000, &[(token_id(9), 1)]), 1, 1),
+            ],
+            vec![
+                ergo_box(1, 1_000_000, &[(token_id(7), 60)]),
+                ergo_box(0, 2_000_000, &[(token_id(7), 40), (token_id(8), 5)]),
+                ergo_box(2, 1_000_000, &[(token_id(9), 1)]),
+            ],
+        )
+    }
+
+    #[test]
+    fn deltas_are_net_changes_per_address() {
+        let token = String::from(token_id(7));
+        let mut expected = vec![
+            (
+                "AddressDeltaApplied",
+                address(0),
+                -1_000_000,
+                BTreeMap::from([(token.clone(), -60)]),
+            ),
+            (
+                "AddressDeltaApplied",
+                address(1),
+                1_000_000,
+                BTreeMap::from([(token, 60)]),
+            ),
+        ];
+        // Deltas are ordered by address. Owner 2 has no net change, nor has owner 0 of the
+        // token it kept.
+        expected.sort();
+        assert_eq!(summary(&applied(payment_tx())), expected);
+    }
+
+    #[test]
+    fn rolled_back_txs_yield_negated_deltas_in_reverse_order() {
+        let negated: Vec<_> = summary(&applied(payment_tx()))
+            .into_iter()
+            .rev()
+            .map(|(_, address, value, tokens)| {
+                (
+                    "AddressDeltaUnapplied",
+                    address,
+                    -value,
+                    tokens
+                        .into_iter()
+                        .map(|(token_id, amount)| (token_id, -amount))
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(summary(&unapplied(payment_tx())), negated);
+    }
+}
@@ -16,6 +16,50 @@ pub enum BlockEvent {
         id: String,
         num_txs: usize,
     },
+    /// The block got enough blocks on top of it to be considered final.
+    BlockFinalized {
+        timestamp: u64,
+        height: u32,
+        id: String,
+        num_txs: usize,
+    },
+    /// Published before the `BlockUnapply` messages of a reorg.
+    ReorgDetected(Reorg),
+    /// Published once the blocks of the new branch of a reorg are applied.
+    ReorgCompleted(Reorg),
+}
+
+/// Blocks rolled back by a reorg along with the blocks replacing them.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Reorg {
+    pub common_ancestor_id: String,
+    pub common_ancestor_height: u32,
+    /// IDs of the rolled back blocks, from the former tip down.
+    pub orphaned_block_ids: Vec<String>,
+    /// IDs of the blocks of the new branch fetched along with the rollback, from the common
+    /// ancestor up.
+    pub replacing_block_ids: Vec<String>,
+}
+
+impl BlockEvent {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            BlockEvent::BlockApply { .. } => "BlockApply",
+            BlockEvent::BlockUnapply { .. } => "BlockUnapply",
+            BlockEvent::BlockFinalized { .. } => "BlockFinalized",
+            BlockEvent::ReorgDetected(_) => "ReorgDetected",
+            BlockEvent::ReorgCompleted(_) => "ReorgCompleted",
+        }
+    }
+
+    pub fn finalized(blk: &Block) -> Self {
+        BlockEvent::BlockFinalized {
+            timestamp: blk.timestamp,
+            height: blk.height,
+            id: base16::encode_lower(blk.id.0 .0.as_ref()),
+            num_txs: blk.transactions.len(),
+        }
+    }
 }
 
 impl From<ChainUpgrade> for BlockEvent {
@@ -27,6 +71,7 @@ impl From<ChainUpgrade> for BlockEvent {
                 height,
                 timestamp,
                 transactions,
+                ..
             }) => {
                 let id: String = base16::encode_lower(id.0 .0.as_ref());
                 BlockEvent::BlockApply {
@@ -42,6 +87,7 @@ impl From<ChainUpgrade> for BlockEvent {
                 height,
                 timestamp,
                 transactions,
+                ..
             }) => {
                 let id: String = base16::encode_lower(id.0 .0.as_ref());
                 BlockEvent::BlockUnapply {
@@ -0,0 +1,238 @@
+use ergo_chain_sync::client::model::BlockInput;
+use ergo_lib::ergotree_ir::chain::ergo_box::ErgoBox;
+
+use crate::models::tx_event::TxEvent;
+
+/// Change of the UTXO set caused by a single box.
+#[derive(Debug, Clone)]
+pub enum BoxEvent {
+    BoxCreated(BoxCreation),
+    BoxSpent(BoxSpending),
+    /// Inverse of [`BoxEvent::BoxCreated`], emitted when the creating tx is rolled back.
+    BoxUncreated(BoxCreation),
+    /// Inverse of [`BoxEvent::BoxSpent`], emitted when the spending tx is rolled back.
+    BoxUnspent(BoxSpending),
+}
+
+#[derive(Debug, Clone)]
+pub struct BoxCreation {
+    pub output: ErgoBox,
+    pub timestamp: i64,
+    pub height: i32,
+    pub block_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct BoxSpending {
+    pub input: BlockInput,
+    pub spending_tx_id: String,
+    /// Index of the box among the inputs of the spending tx.
+    pub input_index: u16,
+    pub timestamp: i64,
+    pub height: i32,
+    pub block_id: String,
+}
+
+impl BoxEvent {
+    /// Box events of the given tx event. An applied tx spends its inputs and then creates
+    /// its outputs. Events of an unapplied tx are the inverses of those, in reverse order.
+    pub fn from_tx_event(ev: TxEvent) -> Vec<BoxEvent> {
+        match ev {
+            TxEvent::AppliedTx {
+                timestamp,
+                tx,
+                block_height,
+                block_id,
+            } => {
+                let spending_tx_id: String = tx.id.into();
+                let spent = tx.inputs.into_iter().enumerate().map(|(ix, input)| {
+                    BoxEvent::BoxSpent(BoxSpending {
+                        input,
+                        spending_tx_id: spending_tx_id.clone(),
+                        input_index: ix as u16,
+                        timestamp,
+                        height: block_height,
+                        block_id: block_id.clone(),
+                    })
+                });
+                let created = tx.outputs.into_iter().map(|output| {
+                    BoxEvent::BoxCreated(BoxCreation {
+                        output,
+                        timestamp,
+                        height: block_height,
+                        block_id: block_id.clone(),
+                    })
+                });
+                spent.chain(created).collect()
+            }
+            TxEvent::UnappliedTx {
+                timestamp,
+                tx,
+                block_height,
+                block_id,
+            } => {
+                let spending_tx_id: String = tx.id.into();
+                let uncreated = tx.outputs.into_iter().rev().map(|output| {
+                    BoxEvent::BoxUncreated(BoxCreation {
+                        output,
+                        timestamp,
+                        height: block_height,
+                        block_id: block_id.clone(),
+                    })
+                });
+                let unspent = tx.inputs.into_iter().enumerate().rev().map(|(ix, input)| {
+                    BoxEvent::BoxUnspent(BoxSpending {
+                        input,
+                        spending_tx_id: spending_tx_id.clone(),
+                        input_index: ix as u16,
+                        timestamp,
+                        height: block_height,
+                        block_id: block_id.clone(),
+                    })
+                });
+                uncreated.chain(unspent).collect()
+            }
+        }
+    }
+
+    pub fn map_input(self, f: impl FnOnce(BlockInput) -> BlockInput) -> Self {
+        match self {
+            BoxEvent::BoxSpent(s) => BoxEvent::BoxSpent(BoxSpending {
+                input: f(s.input),
+                ..s
+            }),
+            BoxEvent::BoxUnspent(s) => BoxEvent::BoxUnspent(BoxSpending {
+                input: f(s.input),
+                ..s
+            }),
+            ev => ev,
+        }
+    }
+
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            BoxEvent::BoxCreated(_) => "BoxCreated",
+            BoxEvent::BoxSpent(_) => "BoxSpent",
+            BoxEvent::BoxUncreated(_) => "BoxUncreated",
+            BoxEvent::BoxUnspent(_) => "BoxUnspent",
+        }
+    }
+
+    pub fn ergo_box(&self) -> &ErgoBox {
+        match self {
+            BoxEvent::BoxCreated(c) | BoxEvent::BoxUncreated(c) => &c.output,
+            BoxEvent::BoxSpent(s) | BoxEvent::BoxUnspent(s) => &s.input.ergo_box,
+        }
+    }
+
+    pub fn height(&self) -> i32 {
+        match self {
+            BoxEvent::BoxCreated(c) | BoxEvent::BoxUncreated(c) => c.height,
+            BoxEvent::BoxSpent(s) | BoxEvent::BoxUnspent(s) => s.height,
+        }
+    }
+
+    pub fn block_id(&self) -> &str {
+        match self {
+            BoxEvent::BoxCreated(c) | BoxEvent::BoxUncreated(c) => &c.block_id,
+            BoxEvent::BoxSpent(s) | BoxEvent::BoxUnspent(s) => &s.block_id,
+        }
+    }
+
+    /// Whether the box is no longer part of the UTXO set after the event.
+    pub fn removes_box(&self) -> bool {
+        matches!(self, BoxEvent::BoxSpent(_) | BoxEvent::BoxUncreated(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_chain_sync::client::model::BlockTransaction;
+    use ergo_lib::ergotree_ir::chain::ergo_box::BoxId;
+
+    use crate::models::box_event::BoxEvent;
+    use crate::models::fixtures::{applied, ergo_box, stamp, tx, unapplied, HEIGHT};
+    use crate::models::tx_event::TxEvent;
+
+    /// Boxes of owners 0 and 1 merged into a box of owner 2.
+    fn merge_tx() -> BlockTransaction {
+        tx(
+            2,
+            vec![
+                stamp(ergo_box(0, 2_000_000, &[]), 1, 0),
+                stamp(ergo_box(1, 1_000_000, &[]), 1, 1),
+            ],
+            vec![ergo_box(2, 3_000_000, &[])],
+        )
+    }
+
+    /// Event type, box ID and input index of every event.
+    fn summary(events: &[BoxEvent]) -> Vec<(&'static str, BoxId, Option<u16>)> {
+        events
+            .iter()
+            .map(|ev| {
+                let input_index = match ev {
+                    BoxEvent::BoxSpent(s) | BoxEvent::BoxUnspent(s) => Some(s.input_index),
+                    _ => None,
+                };
+                (ev.event_type(), ev.ergo_box().box_id(), input_index)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn applied_tx_spends_inputs_then_creates_outputs() {
+        let tx = merge_tx();
+        let inputs: Vec<BoxId> = tx.inputs.iter().map(|i| i.ergo_box.box_id()).collect();
+        let outputs: Vec<BoxId> = tx.outputs.iter().map(|o| o.box_id()).collect();
+        let events = BoxEvent::from_tx_event(applied(tx.clone()));
+        assert_eq!(
+            summary(&events),
+            vec![
+                ("BoxSpent", inputs[0], Some(0)),
+                ("BoxSpent", inputs[1], Some(1)),
+                ("BoxCreated", outputs[0], None),
+            ]
+        );
+        assert!(events
+            .iter()
+            .all(|ev| ev.height() == HEIGHT && ev.block_id() == "block"));
+        assert!(events.iter().all(|ev| match ev {
+            BoxEvent::BoxSpent(s) => s.spending_tx_id == String::from(tx.id),
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn unapplied_tx_yields_exact_inverses_in_reverse_order() {
+        let applied_events = summary(&BoxEvent::from_tx_event(applied(merge_tx())));
+        let inverses: Vec<_> = applied_events
+            .into_iter()
+            .rev()
+            .map(|(event_type, box_id, input_index)| {
+                let inverse = match event_type {
+                    "BoxSpent" => "BoxUnspent",
+                    "BoxCreated" => "BoxUncreated",
+                    other => panic!("Unexpected event {}", other),
+                };
+                (inverse, box_id, input_index)
+            })
+            .collect();
+        assert_eq!(
+            summary(&BoxEvent::from_tx_event(unapplied(merge_tx()))),
+            inverses
+        );
+    }
+
+    #[test]
+    fn spent_and_uncreated_boxes_leave_the_utxo_set() {
+        let removed = |ev: TxEvent| -> Vec<bool> {
+            BoxEvent::from_tx_event(ev)
+                .iter()
+                .map(BoxEvent::removes_box)
+                .collect()
+        };
+        assert_eq!(removed(applied(merge_tx())), vec![true, true, false]);
+        assert_eq!(removed(unapplied(merge_tx())), vec![true, false, false]);
+    }
+}
@@ -1,10 +1,17 @@
-use ergo_chain_sync::client::model::BlockTransaction;
+use std::collections::BTreeMap;
+
+use ergo_chain_sync::client::model::{BlockInput, BlockTransaction};
+use ergo_lib::chain::transaction::prover_result::ProverResult;
 use ergo_lib::chain::transaction::{DataInput, TxId};
 use ergo_lib::ergotree_ir::chain::ergo_box::{BoxId, ErgoBox, NonMandatoryRegisters};
 use ergo_lib::ergotree_ir::chain::token::{Token, TokenId};
-use ergo_lib::ergotree_ir::serialization::SigmaSerializable;
+use ergo_lib::ergotree_ir::serialization::{SigmaSerializable, SigmaSerializationError};
 use serde::Serialize;
 
+use crate::models::address::{encode_address, ergo_tree_template_hash, Network};
+use crate::models::encoding::EncodingError;
+use crate::models::registers::{decode_registers, DecodedRegister};
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CborToken {
@@ -39,27 +46,77 @@ pub struct CborErgoBox {
     box_id: BoxId,
     value: u64,
     ergo_tree: String,
+    /// Base58-encoded address guarded by `ergo_tree`, if it can be derived.
+    address: Option<String>,
+    /// Hash of the ErgoTree template, shared by all boxes of the same contract.
+    ergo_tree_template_hash: Option<String>,
     assets: Vec<CborToken>,
     additional_registers: NonMandatoryRegisters,
+    /// Registers rendered as typed values next to their raw hex.
+    decoded_registers: BTreeMap<String, DecodedRegister>,
     creation_height: u32,
     transaction_id: TxId,
     index: u16,
+    /// Present on inputs only, if proofs are included.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spending_proof: Option<CborSpendingProof>,
 }
 
-impl From<ErgoBox> for CborErgoBox {
-    fn from(b: ErgoBox) -> Self {
-        Self {
+impl CborErgoBox {
+    pub fn new(b: ErgoBox, network: Network) -> Result<Self, EncodingError> {
+        Ok(Self {
             box_id: b.box_id(),
             value: *b.value.as_u64(),
-            ergo_tree: base16::encode_lower(&b.ergo_tree.sigma_serialize_bytes().unwrap()),
+            ergo_tree: base16::encode_lower(&b.ergo_tree.sigma_serialize_bytes()?),
+            address: encode_address(&b.ergo_tree, network),
+            ergo_tree_template_hash: ergo_tree_template_hash(&b.ergo_tree),
             assets: b.tokens.map_or(vec![], |tokens| {
                 tokens.into_iter().map(CborToken::from).collect()
             }),
+            decoded_registers: decode_registers(&b.additional_registers)?,
             additional_registers: b.additional_registers,
             creation_height: b.creation_height,
             transaction_id: b.transaction_id,
             index: b.index,
-        }
+            spending_proof: None,
+        })
+    }
+
+    pub fn from_input(input: BlockInput, network: Network) -> Result<Self, EncodingError> {
+        Ok(Self {
+            spending_proof: input
+                .spending_proof
+                .map(CborSpendingProof::try_from)
+                .transpose()?,
+            ..CborErgoBox::new(input.ergo_box, network)?
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CborSpendingProof {
+    /// Hex-encoded proof, empty if the box is not protected by a sigma proposition.
+    proof_bytes: String,
+    /// Hex-encoded serialized context variables by their ids.
+    extension: BTreeMap<u8, String>,
+}
+
+impl TryFrom<ProverResult> for CborSpendingProof {
+    type Error = SigmaSerializationError;
+
+    fn try_from(pr: ProverResult) -> Result<Self, Self::Error> {
+        Ok(Self {
+            proof_bytes: base16::encode_lower(&Vec::<u8>::from(pr.proof)),
+            extension: pr
+                .extension
+                .values
+                .into_iter()
+                .map(|(id, constant)| {
+                    Ok((id, base16::encode_lower(&constant.sigma_serialize_bytes()?)))
+                })
+                .collect::<Result<_, Self::Error>>()?,
+        })
     }
 }
 
@@ -72,15 +129,23 @@ pub struct CborBlockTransaction {
     outputs: Vec<CborErgoBox>,
 }
 
-impl From<BlockTransaction> for CborBlockTransaction {
-    fn from(tx: BlockTransaction) -> Self {
-        Self {
+impl CborBlockTransaction {
+    pub fn new(tx: BlockTransaction, network: Network) -> Result<Self, EncodingError> {
+        Ok(Self {
             id: tx.id,
-            inputs: tx.inputs.into_iter().map(CborErgoBox::from).collect(),
+            inputs: tx
+                .inputs
+                .into_iter()
+                .map(|input| CborErgoBox::from_input(input, network))
+                .collect::<Result<_, _>>()?,
             data_inputs: tx
                 .data_inputs
                 .map(|di| di.into_iter().map(CborDataInput::from).collect()),
-            outputs: tx.outputs.into_iter().map(CborErgoBox::from).collect(),
-        }
+            outputs: tx
+                .outputs
+                .into_iter()
+                .map(|b| CborErgoBox::new(b, network))
+                .collect::<Result<_, _>>()?,
+        })
     }
 }
@@ -0,0 +1,59 @@
+use ergo_chain_sync::client::model::BlockTransaction;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::sink::{SinkRecord, BLOCK_ID_HEADER, EVENT_TYPE_HEADER, HEIGHT_HEADER};
+
+/// Event which could not be encoded, along with everything needed to investigate it.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetter {
+    /// Topic the event was destined for.
+    pub topic: String,
+    pub event_type: String,
+    pub error: String,
+    pub block_id: Option<String>,
+    pub height: Option<i32>,
+    /// The transaction in the node's JSON representation.
+    pub tx: Value,
+}
+
+impl DeadLetter {
+    pub fn new(
+        topic: impl Into<String>,
+        event_type: impl Into<String>,
+        error: impl ToString,
+        tx: &BlockTransaction,
+    ) -> Self {
+        Self {
+            topic: topic.into(),
+            event_type: event_type.into(),
+            error: error.to_string(),
+            block_id: None,
+            height: None,
+            // Fall back to the debug representation, so that nothing is lost.
+            tx: serde_json::to_value(tx).unwrap_or_else(|_| Value::String(format!("{:?}", tx))),
+        }
+    }
+
+    pub fn in_block(self, block_id: impl Into<String>, height: i32) -> Self {
+        Self {
+            block_id: Some(block_id.into()),
+            height: Some(height),
+            ..self
+        }
+    }
+
+    /// Record of the dead letter keyed by the given `key`.
+    pub fn into_record(self, dead_letter_topic: &str, key: impl Into<String>) -> SinkRecord {
+        // Can't fail, since the letter consists of plain strings, numbers and JSON values only.
+        let value = serde_json::to_string(&self).unwrap();
+        let mut record = SinkRecord::new(dead_letter_topic, key, value)
+            .with_header(EVENT_TYPE_HEADER, &self.event_type);
+        if let (Some(block_id), Some(height)) = (self.block_id, self.height) {
+            record = record
+                .with_header(HEIGHT_HEADER, height)
+                .with_header(BLOCK_ID_HEADER, block_id);
+        }
+        record
+    }
+}
@@ -0,0 +1,219 @@
+use base64::{engine::general_purpose, Engine as _};
+use derive_more::From;
+use ergo_chain_sync::client::model::{BlockInput, BlockTransaction};
+use ergo_lib::ergotree_ir::serialization::{SigmaSerializable, SigmaSerializationError};
+use ergo_mempool_sync::MempoolUpdate;
+use prost::Message;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+
+use crate::models::address::Network;
+use crate::models::address_delta::AddressDeltaEvent;
+use crate::models::block_event::BlockEvent;
+use crate::models::box_event::BoxEvent;
+use crate::models::cbor::{CborBlockTransaction, CborErgoBox};
+use crate::models::header_event::HeaderEvent;
+use crate::models::mempool_event::MempoolEvent;
+use crate::models::proto;
+use crate::models::token_event::TokenEvent;
+use crate::models::tx_event::TxEvent;
+
+/// Encoding of record values of all topics.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// JSON, with transactions encoded according to [`TxEncoding`].
+    #[default]
+    Json,
+    /// Protobuf messages described in `proto/events.proto`.
+    Protobuf,
+}
+
+/// Encoding of transactions within JSON record values.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxEncoding {
+    /// Base64-encoded CBOR of [`CborBlockTransaction`].
+    #[default]
+    CborBase64,
+    /// [`CborBlockTransaction`] as a plain JSON object.
+    Json,
+    /// Hex-encoded sigma-serialized transaction. Inputs are given by box ID only, along with
+    /// their spending proofs if those are included.
+    SigmaBytesHex,
+}
+
+#[derive(Error, From, Debug)]
+pub enum EncodingError {
+    #[error("sigma serialization: {0}")]
+    Sigma(SigmaSerializationError),
+    #[error("cbor: {0}")]
+    Cbor(serde_cbor::Error),
+    #[error("json: {0}")]
+    Json(serde_json::Error),
+}
+
+/// Encodes the events of all topics.
+#[derive(Debug, Clone, Copy)]
+pub struct Encoder {
+    pub encoding: Encoding,
+    pub tx_encoding: TxEncoding,
+    /// Whether inputs of transactions carry their spending proofs and context extensions.
+    pub include_spending_proofs: bool,
+    /// Network addresses of boxes are encoded for.
+    pub network: Network,
+}
+
+impl Encoder {
+    fn prepare_input(&self, input: BlockInput) -> BlockInput {
+        if self.include_spending_proofs {
+            input
+        } else {
+            BlockInput {
+                spending_proof: None,
+                ..input
+            }
+        }
+    }
+
+    fn prepare_tx(&self, tx: BlockTransaction) -> BlockTransaction {
+        BlockTransaction {
+            inputs: tx.inputs.mapped(|input| self.prepare_input(input)),
+            ..tx
+        }
+    }
+
+    pub fn block_event(&self, ev: BlockEvent) -> Vec<u8> {
+        match self.encoding {
+            // Can't fail, since the event consists of plain strings and numbers only.
+            Encoding::Json => serde_json::to_vec(&ev).unwrap(),
+            Encoding::Protobuf => proto::BlockEvent::from(ev).encode_to_vec(),
+        }
+    }
+
+    pub fn header_event(&self, ev: HeaderEvent) -> Vec<u8> {
+        match self.encoding {
+            // Can't fail, since the event consists of plain strings and numbers only.
+            Encoding::Json => serde_json::to_vec(&ev).unwrap(),
+            Encoding::Protobuf => proto::HeaderEvent::from(ev).encode_to_vec(),
+        }
+    }
+
+    pub fn tx_event(&self, ev: TxEvent) -> Result<Vec<u8>, EncodingError> {
+        let ev = ev.map_tx(|tx| self.prepare_tx(tx));
+        match self.encoding {
+            Encoding::Json => {
+                let json = match ev {
+                    TxEvent::AppliedTx {
+                        timestamp,
+                        tx,
+                        block_height,
+                        block_id,
+                    } => json!({
+                        "AppliedEvent": {
+                            "timestamp": timestamp,
+                            "height": block_height,
+                            "tx": self.encode_tx(tx)?,
+                            "block_id": block_id,
+                        }
+                    }),
+                    TxEvent::UnappliedTx {
+                        timestamp,
+                        tx,
+                        block_height,
+                        block_id,
+                    } => json!({
+                        "UnappliedEvent": {
+                            "timestamp": timestamp,
+                            "height": block_height,
+                            "tx": self.encode_tx(tx)?,
+                            "block_id": block_id,
+                        }
+                    }),
+                };
+                Ok(json.to_string().into_bytes())
+            }
+            Encoding::Protobuf => Ok(proto::TxEvent::new(ev, self.network)?.encode_to_vec()),
+        }
+    }
+
+    pub fn box_event(&self, ev: BoxEvent) -> Result<Vec<u8>, EncodingError> {
+        let ev = ev.map_input(|input| self.prepare_input(input));
+        match self.encoding {
+            Encoding::Json => {
+                let event_type = ev.event_type();
+                let body = match ev {
+                    BoxEvent::BoxCreated(c) | BoxEvent::BoxUncreated(c) => json!({
+                        "box": serde_json::to_value(CborErgoBox::new(c.output, self.network)?)?,
+                        "timestamp": c.timestamp,
+                        "height": c.height,
+                        "block_id": c.block_id,
+                    }),
+                    BoxEvent::BoxSpent(s) | BoxEvent::BoxUnspent(s) => json!({
+                        "box": serde_json::to_value(CborErgoBox::from_input(s.input, self.network)?)?,
+                        "spending_tx_id": s.spending_tx_id,
+                        "input_index": s.input_index,
+                        "timestamp": s.timestamp,
+                        "height": s.height,
+                        "block_id": s.block_id,
+                    }),
+                };
+                let mut json = Map::new();
+                json.insert(event_type.to_string(), body);
+                Ok(Value::Object(json).to_string().into_bytes())
+            }
+            Encoding::Protobuf => Ok(proto::BoxEvent::new(ev, self.network)?.encode_to_vec()),
+        }
+    }
+
+    pub fn token_event(&self, ev: TokenEvent) -> Vec<u8> {
+        match self.encoding {
+            Encoding::Json => ev.to_json().to_string().into_bytes(),
+            Encoding::Protobuf => proto::TokenEvent::from(ev).encode_to_vec(),
+        }
+    }
+
+    pub fn address_delta_event(&self, ev: AddressDeltaEvent) -> Vec<u8> {
+        match self.encoding {
+            Encoding::Json => ev.to_json().to_string().into_bytes(),
+            Encoding::Protobuf => proto::AddressDeltaEvent::from(ev).encode_to_vec(),
+        }
+    }
+
+    pub fn mempool_update(&self, update: MempoolUpdate) -> Result<Vec<u8>, EncodingError> {
+        let update = match update {
+            MempoolUpdate::TxAccepted(tx) => MempoolUpdate::TxAccepted(self.prepare_tx(tx)),
+            MempoolUpdate::TxWithdrawn(tx) => MempoolUpdate::TxWithdrawn(self.prepare_tx(tx)),
+            MempoolUpdate::TxConfirmed(tx) => MempoolUpdate::TxConfirmed(self.prepare_tx(tx)),
+        };
+        match self.encoding {
+            Encoding::Json => {
+                let ev = MempoolEvent::new(update, self)?;
+                Ok(serde_json::to_vec(&ev)?)
+            }
+            Encoding::Protobuf => {
+                Ok(proto::MempoolEvent::new(update, self.network)?.encode_to_vec())
+            }
+        }
+    }
+
+    /// Transaction as represented within JSON values.
+    pub fn encode_tx(&self, tx: BlockTransaction) -> Result<Value, EncodingError> {
+        match self.tx_encoding {
+            TxEncoding::CborBase64 => {
+                let cbor_tx = CborBlockTransaction::new(tx, self.network)?;
+                let tx_bytes = serde_cbor::to_vec(&cbor_tx)?;
+                Ok(Value::String(general_purpose::STANDARD.encode(tx_bytes)))
+            }
+            TxEncoding::Json => Ok(serde_json::to_value(CborBlockTransaction::new(
+                tx,
+                self.network,
+            )?)?),
+            TxEncoding::SigmaBytesHex => {
+                let tx_bytes = tx.to_transaction()?.sigma_serialize_bytes()?;
+                Ok(Value::String(base16::encode_lower(&tx_bytes)))
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Small txs for unit tests of the events derived from them.
+
+use ergo_chain_sync::client::model::{BlockInput, BlockTransaction};
+use ergo_lib::chain::transaction::{TxId, TxIoVec};
+use ergo_lib::ergo_chain_types::Digest32;
+use ergo_lib::ergotree_ir::chain::ergo_box::box_value::BoxValue;
+use ergo_lib::ergotree_ir::chain::ergo_box::{BoxId, BoxTokens, ErgoBox, NonMandatoryRegisters};
+use ergo_lib::ergotree_ir::chain::token::{Token, TokenAmount, TokenId};
+use ergo_lib::ergotree_ir::ergo_tree::ErgoTree;
+use ergo_lib::ergotree_ir::serialization::SigmaSerializable;
+use serde_json::Value;
+
+use crate::models::tx_event::TxEvent;
+
+/// Compressed public keys `G`, `2G` and `3G` of secp256k1.
+const PUBLIC_KEYS: [&str; 3] = [
+    "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+    "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+    "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+];
+
+pub const HEIGHT: i32 = 10;
+
+/// P2PK tree of one of three distinct owners, `owner` being 0 to 2.
+pub fn p2pk_tree(owner: usize) -> ErgoTree {
+    let bytes = base16::decode(&format!("0008cd{}", PUBLIC_KEYS[owner])).unwrap();
+    ErgoTree::sigma_parse_bytes(&bytes).unwrap()
+}
+
+pub fn tx_id(seed: u8) -> TxId {
+    TxId(Digest32::from([seed; 32]))
+}
+
+pub fn token_id(seed: u8) -> TokenId {
+    TokenId::from(BoxId::from(Digest32::from([seed; 32])))
+}
+
+/// Box of the given owner, created by the tx of seed 0 at index 0. See [`stamp`] and [`tx`]
+/// for other origins.
+pub fn ergo_box(owner: usize, value: u64, tokens: &[(TokenId, u64)]) -> ErgoBox {
+    let tokens = (!tokens.is_empty()).then(|| {
+        BoxTokens::from_vec(
+            tokens
+                .iter()
+                .map(|(token_id, amount)| Token {
+                    token_id: *token_id,
+                    amount: TokenAmount::try_from(*amount).unwrap(),
+                })
+                .collect(),
+        )
+        .unwrap()
+    });
+    ErgoBox::new(
+        BoxValue::new(value).unwrap(),
+        p2pk_tree(owner),
+        tokens,
+        NonMandatoryRegisters::empty(),
+        1,
+        tx_id(0),
+        0,
+    )
+    .unwrap()
+}
+
+/// The box as created by the tx of `tx_seed` at the given output index.
+pub fn stamp(b: ErgoBox, tx_seed: u8, index: u16) -> ErgoBox {
+    ErgoBox::new(
+        b.value,
+        b.ergo_tree,
+        b.tokens,
+        b.additional_registers,
+        b.creation_height,
+        tx_id(tx_seed),
+        index,
+    )
+    .unwrap()
+}
+
+/// The box with the given registers, mapping register names to hex-encoded constants the way
+/// the node renders them.
+pub fn with_registers(b: ErgoBox, registers: Value) -> ErgoBox {
+    ErgoBox::new(
+        b.value,
+        b.ergo_tree,
+        b.tokens,
+        serde_json::from_value(registers).unwrap(),
+        b.creation_height,
+        b.transaction_id,
+        b.index,
+    )
+    .unwrap()
+}
+
+/// Tx of the given seed spending `inputs` and creating `outputs`, which are stamped as its own.
+pub fn tx(seed: u8, inputs: Vec<ErgoBox>, outputs: Vec<ErgoBox>) -> BlockTransaction {
+    BlockTransaction {
+        id: tx_id(seed),
+        inputs: TxIoVec::from_vec(
+            inputs
+                .into_iter()
+                .map(|ergo_box| BlockInput {
+                    ergo_box,
+                    spending_proof: None,
+                })
+                .collect(),
+        )
+        .unwrap(),
+        data_inputs: None,
+        outputs: TxIoVec::from_vec(
+            outputs
+                .into_iter()
+                .enumerate()
+                .map(|(ix, b)| stamp(b, seed, ix as u16))
+                .collect(),
+        )
+        .unwrap(),
+    }
+}
+
+pub fn applied(tx: BlockTransaction) -> TxEvent {
+    TxEvent::AppliedTx {
+        timestamp: 0,
+        tx,
+        block_height: HEIGHT,
+        block_id: "block".to_string(),
+    }
+}
+
+pub fn unapplied(tx: BlockTransaction) -> TxEvent {
+    TxEvent::UnappliedTx {
+        timestamp: 0,
+        tx,
+        block_height: HEIGHT,
+        block_id: "block".to_string(),
+    }
+}
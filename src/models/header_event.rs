@@ -0,0 +1,258 @@
+use std::sync::OnceLock;
+
+use ergo_chain_sync::client::model::BlockExtension;
+use ergo_chain_sync::model::Block;
+use ergo_chain_sync::ChainUpgrade;
+use ergo_lib::ergotree_ir::chain::address::AddressEncoder;
+use ergo_lib::ergotree_ir::serialization::SigmaSerializable;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::models::address::Network;
+
+/// Addresses of the contract guarding the outputs paying tx fees to miners, by network.
+const MAINNET_FEE_ADDRESS: &str = "2iHkR7CWvD1R4j1yZg5bkeDRQavjAaVPeTDFGGLZduHyfWMuYpmhHocX8GJoaieTx78FntzJbCBVL6rf96ocJoZdmWBL2fci7NqWgAirppPQmZ7fN9V6z13Ay6brPriBKYqLp1bT2Fk4FkFLCfdPpe";
+const TESTNET_FEE_ADDRESS: &str = "Bf1X9JgQTUtgntaer91B24n6kP8L2kqEiQqNf1z97BKo9UbnW3WRP9VXu8BXd1LsYCiYbHJEdWKxkF5YNx5n7m31wsDjbEuB3B13ZMDVBWkepGmWfGa71otpFViHDCuvbw1uNicAQnfuWfnj8fbCa4";
+
+/// Serialized ErgoTree of the fee contract of the given network, decoded once.
+fn fee_ergo_tree(network: Network) -> &'static [u8] {
+    static MAINNET: OnceLock<Vec<u8>> = OnceLock::new();
+    static TESTNET: OnceLock<Vec<u8>> = OnceLock::new();
+    let (tree, address) = match network {
+        Network::Mainnet => (&MAINNET, MAINNET_FEE_ADDRESS),
+        Network::Testnet => (&TESTNET, TESTNET_FEE_ADDRESS),
+    };
+    tree.get_or_init(|| {
+        AddressEncoder::new(network.into())
+            .parse_address_from_str(address)
+            .unwrap()
+            .script()
+            .unwrap()
+            .sigma_serialize_bytes()
+            .unwrap()
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum HeaderEvent {
+    HeaderApply(BlockHeader),
+    HeaderUnapply(BlockHeader),
+}
+
+/// Block header along with a summary of the block's contents.
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockHeader {
+    pub id: String,
+    pub parent_id: String,
+    pub version: u8,
+    pub height: u32,
+    pub timestamp: u64,
+    /// Difficulty in compact form.
+    pub n_bits: u64,
+    /// Decimal difficulty decoded from `n_bits`, if it is well-formed.
+    pub difficulty: Option<String>,
+    /// Hex-encoded votes for changes of protocol parameters.
+    pub votes: String,
+    /// Hex-encoded public key of the miner.
+    pub miner_pk: String,
+    pub transactions_root: String,
+    pub extension_root: String,
+    pub ad_proofs_root: String,
+    pub state_root: String,
+    /// Size of the block in bytes, if reported by the node.
+    pub size: Option<u32>,
+    pub num_txs: usize,
+    /// NanoERG paid to miners as fees by all txs of the block.
+    pub total_fees: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extension: Option<BlockExtension>,
+}
+
+impl HeaderEvent {
+    /// Event of the block of the given upgrade, unless the header of the block is unknown.
+    pub fn new(upgrade: &ChainUpgrade, network: Network) -> Option<Self> {
+        match upgrade {
+            ChainUpgrade::RollForward(blk) => {
+                BlockHeader::new(blk, network).map(HeaderEvent::HeaderApply)
+            }
+            ChainUpgrade::RollBackward(blk) => {
+                BlockHeader::new(blk, network).map(HeaderEvent::HeaderUnapply)
+            }
+        }
+    }
+
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            HeaderEvent::HeaderApply(_) => "HeaderApply",
+            HeaderEvent::HeaderUnapply(_) => "HeaderUnapply",
+        }
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        match self {
+            HeaderEvent::HeaderApply(h) | HeaderEvent::HeaderUnapply(h) => h,
+        }
+    }
+
+    pub fn with_extension(self, extension: BlockExtension) -> Self {
+        match self {
+            HeaderEvent::HeaderApply(h) => HeaderEvent::HeaderApply(BlockHeader {
+                extension: Some(extension),
+                ..h
+            }),
+            HeaderEvent::HeaderUnapply(h) => HeaderEvent::HeaderUnapply(BlockHeader {
+                extension: Some(extension),
+                ..h
+            }),
+        }
+    }
+}
+
+impl BlockHeader {
+    fn new(blk: &Block, network: Network) -> Option<Self> {
+        let header = blk.header.as_ref()?;
+        Some(Self {
+            id: base16::encode_lower(header.id.0 .0.as_ref()),
+            parent_id: base16::encode_lower(header.parent_id.0 .0.as_ref()),
+            version: header.version,
+            height: header.height,
+            timestamp: header.timestamp,
+            n_bits: header.n_bits,
+            difficulty: decode_difficulty(header.n_bits).map(|d| d.to_string()),
+            votes: node_repr(&header.votes),
+            miner_pk: node_repr(&header.autolykos_solution.miner_pk),
+            transactions_root: node_repr(&header.transaction_root),
+            extension_root: node_repr(&header.extension_root),
+            ad_proofs_root: node_repr(&header.ad_proofs_root),
+            state_root: node_repr(&header.state_root),
+            size: blk.size,
+            num_txs: blk.transactions.len(),
+            total_fees: total_fees(blk, network),
+            extension: None,
+        })
+    }
+}
+
+/// NanoERG paid to the fee contract of the given network by all txs of the block.
+fn total_fees(blk: &Block, network: Network) -> u64 {
+    let fee_tree = fee_ergo_tree(network);
+    blk.transactions
+        .iter()
+        .flat_map(|tx| tx.outputs.iter())
+        .filter(|b| {
+            b.ergo_tree
+                .sigma_serialize_bytes()
+                .map_or(false, |bytes| bytes == fee_tree)
+        })
+        .map(|b| *b.value.as_u64())
+        .sum()
+}
+
+/// Decodes difficulty from its compact form: a 3-byte mantissa scaled by 256^(exponent - 3),
+/// the exponent being the most significant byte.
+fn decode_difficulty(n_bits: u64) -> Option<u128> {
+    let exponent = ((n_bits >> 24) & 0xff) as u32;
+    let mantissa = (n_bits & 0x007fffff) as u128;
+    // Negative difficulty is invalid.
+    if n_bits & 0x00800000 != 0 {
+        return None;
+    }
+    if exponent <= 3 {
+        Some(mantissa >> (8 * (3 - exponent)))
+    } else {
+        let shift = 8 * (exponent - 3);
+        let difficulty = mantissa.checked_shl(shift)?;
+        // Bits shifted out mean overflow.
+        (difficulty >> shift == mantissa).then_some(difficulty)
+    }
+}
+
+/// The value the way the node's API renders it, i.e. mostly hex.
+fn node_repr<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(s)) => s,
+        Ok(other) => other.to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_chain_sync::model::Block;
+    use ergo_lib::ergo_chain_types::{BlockId, Digest32};
+    use ergo_lib::ergotree_ir::chain::ergo_box::ErgoBox;
+    use ergo_lib::ergotree_ir::ergo_tree::ErgoTree;
+    use ergo_lib::ergotree_ir::serialization::SigmaSerializable;
+
+    use crate::models::address::Network;
+    use crate::models::fixtures::{ergo_box, tx};
+    use crate::models::header_event::{decode_difficulty, fee_ergo_tree, total_fees};
+
+    const FEE_ERGO_TREE: &str = "1005040004000e36100204a00b08cd0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ea02d192a39a8cc7a701730073011001020402d19683030193a38cc7b2a57300000193c2b2a57301007473027303830108cdeeac93b1a57304";
+
+    fn fee_box(value: u64) -> ErgoBox {
+        let b = ergo_box(0, value, &[]);
+        ErgoBox::new(
+            b.value,
+            ErgoTree::sigma_parse_bytes(fee_ergo_tree(Network::Mainnet)).unwrap(),
+            b.tokens,
+            b.additional_registers,
+            b.creation_height,
+            b.transaction_id,
+            b.index,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn fee_contract_is_decoded_from_the_address_of_the_network() {
+        // Both networks share the contract, only the address prefix differs.
+        for network in [Network::Mainnet, Network::Testnet] {
+            assert_eq!(base16::encode_lower(fee_ergo_tree(network)), FEE_ERGO_TREE);
+        }
+    }
+
+    #[test]
+    fn fees_are_summed_over_all_txs() {
+        let blk = Block {
+            id: BlockId(Digest32::zero()),
+            parent_id: BlockId(Digest32::zero()),
+            height: 1,
+            timestamp: 0,
+            transactions: vec![
+                tx(
+                    2,
+                    vec![ergo_box(0, 7_000_000, &[])],
+                    vec![ergo_box(1, 6_000_000, &[]), fee_box(1_000_000)],
+                ),
+                tx(
+                    3,
+                    vec![ergo_box(1, 4_100_000, &[])],
+                    vec![ergo_box(2, 2_000_000, &[]), fee_box(2_100_000)],
+                ),
+            ],
+            header: None,
+            size: None,
+        };
+        for network in [Network::Mainnet, Network::Testnet] {
+            assert_eq!(total_fees(&blk, network), 3_100_000);
+        }
+    }
+
+    #[test]
+    fn difficulty_is_decoded_from_compact_form() {
+        assert_eq!(decode_difficulty(0x0312_3456), Some(0x12_3456));
+        assert_eq!(decode_difficulty(0x0112_3456), Some(0x12));
+        assert_eq!(decode_difficulty(0x0400_0001), Some(0x100));
+        assert_eq!(decode_difficulty(0x1012_3456), Some(0x12_3456 << 104));
+    }
+
+    #[test]
+    fn malformed_difficulty_is_rejected() {
+        // Sign bit set.
+        assert_eq!(decode_difficulty(0x0480_0000), None);
+        // Overflowing 128 bits.
+        assert_eq!(decode_difficulty(0x1112_3456), None);
+        assert_eq!(decode_difficulty(0xff12_3456), None);
+    }
+}
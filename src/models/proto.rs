@@ -0,0 +1,391 @@
+//! Messages generated from `proto/events.proto`, along with conversions from the node's types.
+
+use ergo_chain_sync::client::model::{BlockInput, BlockTransaction};
+use ergo_lib::chain::transaction::prover_result::ProverResult;
+use ergo_lib::ergotree_ir::serialization::SigmaSerializable;
+use ergo_mempool_sync::MempoolUpdate;
+
+use crate::models::address::{encode_address, ergo_tree_template_hash, Network};
+use crate::models::encoding::EncodingError;
+use crate::models::registers::{decode_registers, raw_registers};
+
+include!(concat!(env!("OUT_DIR"), "/ergo_streaming.v1.rs"));
+
+impl From<crate::models::block_event::BlockEvent> for BlockEvent {
+    fn from(ev: crate::models::block_event::BlockEvent) -> Self {
+        use crate::models::block_event::BlockEvent as Ev;
+        let event = match ev {
+            Ev::BlockApply {
+                timestamp,
+                height,
+                id,
+                num_txs,
+            } => block_event::Event::BlockApply(BlockInfo {
+                timestamp,
+                height,
+                id,
+                num_txs: num_txs as u64,
+            }),
+            Ev::BlockUnapply {
+                timestamp,
+                height,
+                id,
+                num_txs,
+            } => block_event::Event::BlockUnapply(BlockInfo {
+                timestamp,
+                height,
+                id,
+                num_txs: num_txs as u64,
+            }),
+            Ev::BlockFinalized {
+                timestamp,
+                height,
+                id,
+                num_txs,
+            } => block_event::Event::BlockFinalized(BlockInfo {
+                timestamp,
+                height,
+                id,
+                num_txs: num_txs as u64,
+            }),
+            Ev::ReorgDetected(reorg) => block_event::Event::ReorgDetected(reorg.into()),
+            Ev::ReorgCompleted(reorg) => block_event::Event::ReorgCompleted(reorg.into()),
+        };
+        Self { event: Some(event) }
+    }
+}
+
+impl From<crate::models::block_event::Reorg> for Reorg {
+    fn from(reorg: crate::models::block_event::Reorg) -> Self {
+        Self {
+            common_ancestor_id: reorg.common_ancestor_id,
+            common_ancestor_height: reorg.common_ancestor_height,
+            orphaned_block_ids: reorg.orphaned_block_ids,
+            replacing_block_ids: reorg.replacing_block_ids,
+        }
+    }
+}
+
+impl From<crate::models::header_event::HeaderEvent> for HeaderEvent {
+    fn from(ev: crate::models::header_event::HeaderEvent) -> Self {
+        use crate::models::header_event::HeaderEvent as Ev;
+        let event = match ev {
+            Ev::HeaderApply(h) => header_event::Event::HeaderApply(h.into()),
+            Ev::HeaderUnapply(h) => header_event::Event::HeaderUnapply(h.into()),
+        };
+        Self { event: Some(event) }
+    }
+}
+
+impl From<crate::models::header_event::BlockHeader> for BlockHeader {
+    fn from(h: crate::models::header_event::BlockHeader) -> Self {
+        Self {
+            id: h.id,
+            parent_id: h.parent_id,
+            version: h.version as u32,
+            height: h.height,
+            timestamp: h.timestamp,
+            n_bits: h.n_bits,
+            difficulty: h.difficulty.unwrap_or_default(),
+            votes: h.votes,
+            miner_pk: h.miner_pk,
+            transactions_root: h.transactions_root,
+            extension_root: h.extension_root,
+            ad_proofs_root: h.ad_proofs_root,
+            state_root: h.state_root,
+            size: h.size,
+            num_txs: h.num_txs as u64,
+            total_fees: h.total_fees,
+            extension: h.extension.map(|ext| BlockExtension {
+                digest: ext.digest,
+                fields: ext
+                    .fields
+                    .into_iter()
+                    .map(|(key, value)| ExtensionField { key, value })
+                    .collect(),
+            }),
+        }
+    }
+}
+
+impl TxEvent {
+    pub fn new(
+        ev: crate::models::tx_event::TxEvent,
+        network: Network,
+    ) -> Result<Self, EncodingError> {
+        use crate::models::tx_event::TxEvent as Ev;
+        let event = match ev {
+            Ev::AppliedTx {
+                timestamp,
+                tx,
+                block_height,
+                block_id,
+            } => tx_event::Event::Applied(TxInfo {
+                timestamp,
+                height: block_height,
+                tx: Some(Transaction::new(tx, network)?),
+                block_id,
+            }),
+            Ev::UnappliedTx {
+                timestamp,
+                tx,
+                block_height,
+                block_id,
+            } => tx_event::Event::Unapplied(TxInfo {
+                timestamp,
+                height: block_height,
+                tx: Some(Transaction::new(tx, network)?),
+                block_id,
+            }),
+        };
+        Ok(Self { event: Some(event) })
+    }
+}
+
+impl BoxEvent {
+    pub fn new(
+        ev: crate::models::box_event::BoxEvent,
+        network: Network,
+    ) -> Result<Self, EncodingError> {
+        use crate::models::box_event::BoxEvent as Ev;
+        let event = match ev {
+            Ev::BoxCreated(c) => box_event::Event::BoxCreated(BoxCreation::new(c, network)?),
+            Ev::BoxSpent(s) => box_event::Event::BoxSpent(BoxSpending::new(s, network)?),
+            Ev::BoxUncreated(c) => box_event::Event::BoxUncreated(BoxCreation::new(c, network)?),
+            Ev::BoxUnspent(s) => box_event::Event::BoxUnspent(BoxSpending::new(s, network)?),
+        };
+        Ok(Self { event: Some(event) })
+    }
+}
+
+impl BoxCreation {
+    fn new(
+        c: crate::models::box_event::BoxCreation,
+        network: Network,
+    ) -> Result<Self, EncodingError> {
+        Ok(Self {
+            output: Some(ErgoBox::new(c.output, network)?),
+            timestamp: c.timestamp,
+            height: c.height,
+            block_id: c.block_id,
+        })
+    }
+}
+
+impl BoxSpending {
+    fn new(
+        s: crate::models::box_event::BoxSpending,
+        network: Network,
+    ) -> Result<Self, EncodingError> {
+        Ok(Self {
+            input: Some(ErgoBox::from_input(s.input, network)?),
+            spending_tx_id: s.spending_tx_id,
+            input_index: s.input_index as u32,
+            timestamp: s.timestamp,
+            height: s.height,
+            block_id: s.block_id,
+        })
+    }
+}
+
+impl From<crate::models::token_event::TokenEvent> for TokenEvent {
+    fn from(ev: crate::models::token_event::TokenEvent) -> Self {
+        use crate::models::token_event::TokenEvent as Ev;
+        let event = match ev {
+            Ev::TokenMinted(m) => token_event::Event::TokenMinted(m.into()),
+            Ev::TokenBurned(b) => token_event::Event::TokenBurned(b.into()),
+            Ev::TokenTransferred(t) => token_event::Event::TokenTransferred(t.into()),
+            Ev::TokenUnminted(m) => token_event::Event::TokenUnminted(m.into()),
+            Ev::TokenUnburned(b) => token_event::Event::TokenUnburned(b.into()),
+            Ev::TokenUntransferred(t) => token_event::Event::TokenUntransferred(t.into()),
+        };
+        Self { event: Some(event) }
+    }
+}
+
+impl From<crate::models::tx_event::TxContext> for TxContext {
+    fn from(c: crate::models::tx_event::TxContext) -> Self {
+        Self {
+            tx_id: c.tx_id,
+            timestamp: c.timestamp,
+            height: c.height,
+            block_id: c.block_id,
+        }
+    }
+}
+
+impl From<crate::models::token_event::TokenMint> for TokenMint {
+    fn from(m: crate::models::token_event::TokenMint) -> Self {
+        Self {
+            token_id: m.token_id,
+            amount: m.amount,
+            name: m.name,
+            description: m.description,
+            decimals: m.decimals,
+            box_id: m.box_id,
+            context: Some(m.context.into()),
+        }
+    }
+}
+
+impl From<crate::models::token_event::TokenBurn> for TokenBurn {
+    fn from(b: crate::models::token_event::TokenBurn) -> Self {
+        Self {
+            token_id: b.token_id,
+            amount: b.amount,
+            context: Some(b.context.into()),
+        }
+    }
+}
+
+impl From<crate::models::token_event::TokenTransfer> for TokenTransfer {
+    fn from(t: crate::models::token_event::TokenTransfer) -> Self {
+        Self {
+            token_id: t.token_id,
+            address: t.address,
+            amount: t.amount,
+            context: Some(t.context.into()),
+        }
+    }
+}
+
+impl From<crate::models::address_delta::AddressDeltaEvent> for AddressDeltaEvent {
+    fn from(ev: crate::models::address_delta::AddressDeltaEvent) -> Self {
+        use crate::models::address_delta::AddressDeltaEvent as Ev;
+        let event = match ev {
+            Ev::AddressDeltaApplied(d) => address_delta_event::Event::AddressDeltaApplied(d.into()),
+            Ev::AddressDeltaUnapplied(d) => {
+                address_delta_event::Event::AddressDeltaUnapplied(d.into())
+            }
+        };
+        Self { event: Some(event) }
+    }
+}
+
+impl From<crate::models::address_delta::AddressDelta> for AddressDelta {
+    fn from(d: crate::models::address_delta::AddressDelta) -> Self {
+        Self {
+            address: d.address,
+            value: d.value,
+            tokens: d.tokens.into_iter().collect(),
+            context: Some(d.context.into()),
+        }
+    }
+}
+
+impl MempoolEvent {
+    pub fn new(update: MempoolUpdate, network: Network) -> Result<Self, EncodingError> {
+        let event = match update {
+            MempoolUpdate::TxAccepted(tx) => mempool_event::Event::TxAccepted(TxAccepted {
+                tx: Some(Transaction::new(tx, network)?),
+            }),
+            MempoolUpdate::TxWithdrawn(tx) => mempool_event::Event::TxWithdrawn(TxWithdrawn {
+                tx: Some(Transaction::new(tx, network)?),
+                confirmed: false,
+            }),
+            MempoolUpdate::TxConfirmed(tx) => mempool_event::Event::TxWithdrawn(TxWithdrawn {
+                tx: Some(Transaction::new(tx, network)?),
+                confirmed: true,
+            }),
+        };
+        Ok(Self { event: Some(event) })
+    }
+}
+
+impl Transaction {
+    pub fn new(tx: BlockTransaction, network: Network) -> Result<Self, EncodingError> {
+        Ok(Self {
+            id: tx.id.into(),
+            inputs: tx
+                .inputs
+                .into_iter()
+                .map(|input| ErgoBox::from_input(input, network))
+                .collect::<Result<_, _>>()?,
+            data_inputs: tx.data_inputs.map_or(vec![], |di| {
+                di.into_iter()
+                    .map(|di| DataInput {
+                        box_id: di.box_id.into(),
+                    })
+                    .collect()
+            }),
+            outputs: tx
+                .outputs
+                .into_iter()
+                .map(|b| ErgoBox::new(b, network))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl ErgoBox {
+    pub fn new(
+        b: ergo_lib::ergotree_ir::chain::ergo_box::ErgoBox,
+        network: Network,
+    ) -> Result<Self, EncodingError> {
+        Ok(Self {
+            address: encode_address(&b.ergo_tree, network).unwrap_or_default(),
+            ergo_tree_template_hash: ergo_tree_template_hash(&b.ergo_tree).unwrap_or_default(),
+            box_id: b.box_id().into(),
+            value: *b.value.as_u64(),
+            ergo_tree: base16::encode_lower(&b.ergo_tree.sigma_serialize_bytes()?),
+            assets: b.tokens.map_or(vec![], |tokens| {
+                tokens
+                    .into_iter()
+                    .map(|t| Token {
+                        token_id: t.token_id.into(),
+                        amount: *t.amount.as_u64(),
+                    })
+                    .collect()
+            }),
+            additional_registers: raw_registers(&b.additional_registers)?
+                .into_iter()
+                .collect(),
+            decoded_registers: decode_registers(&b.additional_registers)?
+                .into_iter()
+                .map(|(name, reg)| {
+                    let register = Register {
+                        r#type: reg.tpe.unwrap_or_default(),
+                        value_json: reg.value.to_string(),
+                        raw: reg.raw,
+                    };
+                    (name, register)
+                })
+                .collect(),
+            creation_height: b.creation_height,
+            transaction_id: b.transaction_id.into(),
+            index: b.index as u32,
+            spending_proof: None,
+        })
+    }
+
+    pub fn from_input(input: BlockInput, network: Network) -> Result<Self, EncodingError> {
+        Ok(Self {
+            spending_proof: input
+                .spending_proof
+                .map(SpendingProof::try_from)
+                .transpose()?,
+            ..ErgoBox::new(input.ergo_box, network)?
+        })
+    }
+}
+
+impl TryFrom<ProverResult> for SpendingProof {
+    type Error = EncodingError;
+
+    fn try_from(pr: ProverResult) -> Result<Self, Self::Error> {
+        Ok(Self {
+            proof_bytes: base16::encode_lower(&Vec::<u8>::from(pr.proof)),
+            extension: pr
+                .extension
+                .values
+                .into_iter()
+                .map(|(id, constant)| {
+                    Ok((
+                        id as u32,
+                        base16::encode_lower(&constant.sigma_serialize_bytes()?),
+                    ))
+                })
+                .collect::<Result<_, Self::Error>>()?,
+        })
+    }
+}
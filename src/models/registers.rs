@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+
+use ergo_lib::ergotree_ir::chain::ergo_box::NonMandatoryRegisters;
+use ergo_lib::ergotree_ir::mir::constant::{Constant, Literal};
+use ergo_lib::ergotree_ir::mir::value::{CollKind, NativeColl};
+use ergo_lib::ergotree_ir::serialization::SigmaSerializable;
+use ergo_lib::ergotree_ir::types::stype::SType;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Register value rendered for consumers which have no sigma interpreter at hand.
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodedRegister {
+    /// Type of the value, e.g. `Coll[Byte]`. Absent if the value can't be parsed.
+    #[serde(rename = "type")]
+    pub tpe: Option<String>,
+    pub value: Value,
+    /// Hex-encoded serialized constant.
+    pub raw: String,
+}
+
+/// Hex-encoded serialized constants by register name (R4 to R9).
+pub fn raw_registers(
+    regs: &NonMandatoryRegisters,
+) -> Result<BTreeMap<String, String>, serde_json::Error> {
+    // Registers serialize to an object mapping register names to hex-encoded constants,
+    // same as in the node API.
+    match serde_json::to_value(regs)? {
+        Value::Object(regs) => Ok(regs
+            .into_iter()
+            .filter_map(|(name, value)| value.as_str().map(|hex| (name, hex.to_string())))
+            .collect()),
+        _ => Ok(BTreeMap::new()),
+    }
+}
+
+pub fn decode_registers(
+    regs: &NonMandatoryRegisters,
+) -> Result<BTreeMap<String, DecodedRegister>, serde_json::Error> {
+    Ok(raw_registers(regs)?
+        .into_iter()
+        .map(|(name, raw)| (name, decode_register(raw)))
+        .collect())
+}
+
+fn decode_register(raw: String) -> DecodedRegister {
+    let constant = base16::decode(&raw)
+        .ok()
+        .and_then(|bytes| Constant::sigma_parse_bytes(&bytes).ok());
+    match constant {
+        Some(Constant { tpe, v }) => DecodedRegister {
+            tpe: Some(type_name(&tpe)),
+            value: render(&v),
+            raw,
+        },
+        None => DecodedRegister {
+            tpe: None,
+            value: Value::Null,
+            raw,
+        },
+    }
+}
+
+/// Name of the type as written in ErgoScript.
+fn type_name(tpe: &SType) -> String {
+    match tpe {
+        SType::SAny => "Any".to_string(),
+        SType::SUnit => "Unit".to_string(),
+        SType::SBoolean => "Boolean".to_string(),
+        SType::SByte => "Byte".to_string(),
+        SType::SShort => "Short".to_string(),
+        SType::SInt => "Int".to_string(),
+        SType::SLong => "Long".to_string(),
+        SType::SBigInt => "BigInt".to_string(),
+        SType::SGroupElement => "GroupElement".to_string(),
+        SType::SSigmaProp => "SigmaProp".to_string(),
+        SType::SBox => "Box".to_string(),
+        SType::SAvlTree => "AvlTree".to_string(),
+        SType::SOption(elem) => format!("Option[{}]", type_name(elem)),
+        SType::SColl(elem) => format!("Coll[{}]", type_name(elem)),
+        SType::STuple(tuple) => format!(
+            "({})",
+            tuple
+                .items
+                .iter()
+                .map(type_name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        other => format!("{:?}", other),
+    }
+}
+
+fn render(v: &Literal) -> Value {
+    match v {
+        Literal::Unit => Value::Null,
+        Literal::Boolean(b) => json!(b),
+        Literal::Byte(b) => json!(b),
+        Literal::Short(s) => json!(s),
+        Literal::Int(i) => json!(i),
+        Literal::Long(l) => json!(l),
+        Literal::GroupElement(ge) => render_serialized(ge.sigma_serialize_bytes()),
+        Literal::SigmaProp(sp) => render_serialized(sp.sigma_serialize_bytes()),
+        Literal::Coll(CollKind::NativeColl(NativeColl::CollByte(bytes))) => {
+            let bytes: Vec<u8> = bytes.iter().map(|b| *b as u8).collect();
+            json!({
+                "hex": base16::encode_lower(&bytes),
+                "utf8": String::from_utf8(bytes).ok(),
+            })
+        }
+        Literal::Coll(CollKind::WrappedColl { items, .. }) => {
+            Value::Array(items.iter().map(render).collect())
+        }
+        Literal::Opt(opt) => opt.as_ref().as_ref().map_or(Value::Null, render),
+        Literal::Tup(items) => Value::Array(items.iter().map(render).collect()),
+        // Rare in registers, left to consumers to decode from the raw value.
+        other => Value::String(format!("{:?}", other)),
+    }
+}
+
+fn render_serialized<E>(bytes: Result<Vec<u8>, E>) -> Value {
+    bytes.map_or(Value::Null, |bytes| {
+        Value::String(base16::encode_lower(&bytes))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::decode_register;
+
+    /// Type and rendered value of the hex-encoded constant.
+    fn decode(raw: &str) -> (Option<String>, serde_json::Value) {
+        let decoded = decode_register(raw.to_string());
+        assert_eq!(decoded.raw, raw);
+        (decoded.tpe, decoded.value)
+    }
+
+    #[test]
+    fn byte_collections_are_rendered_as_hex_and_utf8() {
+        assert_eq!(
+            decode("0e03616263"),
+            (
+                Some("Coll[Byte]".to_string()),
+                json!({"hex": "616263", "utf8": "abc"})
+            )
+        );
+        assert_eq!(
+            decode("0e02ff00"),
+            (
+                Some("Coll[Byte]".to_string()),
+                json!({"hex": "ff00", "utf8": null})
+            )
+        );
+    }
+
+    #[test]
+    fn numbers_are_rendered_as_json_numbers() {
+        assert_eq!(decode("0454"), (Some("Int".to_string()), json!(42)));
+        assert_eq!(decode("05d00f"), (Some("Long".to_string()), json!(1000)));
+    }
+
+    #[test]
+    fn group_elements_are_rendered_as_hex() {
+        let point = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        assert_eq!(
+            decode(&format!("07{}", point)),
+            (Some("GroupElement".to_string()), json!(point))
+        );
+    }
+
+    #[test]
+    fn tuples_and_collections_are_rendered_as_arrays() {
+        assert_eq!(
+            decode("400e020161"),
+            (
+                Some("(Int, Coll[Byte])".to_string()),
+                json!([1, {"hex": "61", "utf8": "a"}])
+            )
+        );
+        assert_eq!(
+            decode("10020201"),
+            (Some("Coll[Int]".to_string()), json!([1, -1]))
+        );
+        assert_eq!(
+            decode("1a02016100"),
+            (
+                Some("Coll[Coll[Byte]]".to_string()),
+                json!([{"hex": "61", "utf8": "a"}, {"hex": "", "utf8": ""}])
+            )
+        );
+        assert_eq!(
+            decode("0c400e01020161"),
+            (
+                Some("Coll[(Int, Coll[Byte])]".to_string()),
+                json!([[1, {"hex": "61", "utf8": "a"}]])
+            )
+        );
+    }
+
+    #[test]
+    fn unparseable_values_fall_back_to_raw_hex() {
+        assert_eq!(decode("ff"), (None, serde_json::Value::Null));
+        assert_eq!(decode("not hex"), (None, serde_json::Value::Null));
+    }
+}
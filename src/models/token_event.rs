@@ -0,0 +1,351 @@
+use std::collections::BTreeMap;
+
+use ergo_lib::ergotree_ir::chain::ergo_box::ErgoBox;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::models::address::{encode_address_or_tree, Network};
+use crate::models::registers::{decode_registers, DecodedRegister};
+use crate::models::tx_event::{TxContext, TxEvent};
+
+/// Change of a token's supply or holders caused by a tx.
+#[derive(Debug, Clone)]
+pub enum TokenEvent {
+    TokenMinted(TokenMint),
+    TokenBurned(TokenBurn),
+    TokenTransferred(TokenTransfer),
+    /// Inverse of [`TokenEvent::TokenMinted`], emitted when the minting tx is rolled back.
+    TokenUnminted(TokenMint),
+    /// Inverse of [`TokenEvent::TokenBurned`], emitted when the burning tx is rolled back.
+    TokenUnburned(TokenBurn),
+    /// Inverse of [`TokenEvent::TokenTransferred`], emitted when the tx is rolled back.
+    TokenUntransferred(TokenTransfer),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenMint {
+    pub token_id: String,
+    /// Emission amount, i.e. the amount across all outputs of the minting tx.
+    pub amount: u64,
+    /// EIP-4 metadata, decoded from R4, R5 and R6 of the first output holding the token.
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub decimals: Option<u32>,
+    /// The first output holding the token.
+    pub box_id: String,
+    #[serde(flatten)]
+    pub context: TxContext,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenBurn {
+    pub token_id: String,
+    /// Amount spent by the tx, but not found in its outputs.
+    pub amount: u64,
+    #[serde(flatten)]
+    pub context: TxContext,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenTransfer {
+    pub token_id: String,
+    pub address: String,
+    /// Net change of the amount held by the address, negative if the address sent the token.
+    /// Mints and burns are included.
+    pub amount: i64,
+    #[serde(flatten)]
+    pub context: TxContext,
+}
+
+impl TokenEvent {
+    /// Token events of the given tx event: mints, then transfers, then burns. Events of an
+    /// unapplied tx are the inverses of those, in reverse order.
+    pub fn from_tx_event(ev: &TxEvent, network: Network) -> Vec<TokenEvent> {
+        let applied = matches!(ev, TxEvent::AppliedTx { .. });
+        let context = ev.context();
+        let tx = ev.tx();
+        let inputs: Vec<&ErgoBox> = tx.inputs.iter().map(|input| &input.ergo_box).collect();
+
+        // Amounts spent and created by token, and net changes by token and address.
+        let mut totals: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        let mut changes: BTreeMap<(String, String), i64> = BTreeMap::new();
+        for (b, spent) in inputs
+            .iter()
+            .map(|b| (*b, true))
+            .chain(tx.outputs.iter().map(|b| (b, false)))
+        {
+            let address = encode_address_or_tree(&b.ergo_tree, network);
+            for token in b.tokens.iter().flat_map(|tokens| tokens.iter()) {
+                let token_id: String = token.token_id.into();
+                let amount = *token.amount.as_u64();
+                let (total_spent, total_created) = totals.entry(token_id.clone()).or_default();
+                let change = changes.entry((token_id, address.clone())).or_default();
+                if spent {
+                    *total_spent += amount;
+                    *change -= amount as i64;
+                } else {
+                    *total_created += amount;
+                    *change += amount as i64;
+                }
+            }
+        }
+
+        // A new token gets the ID of the first input.
+        let minted_id: Option<String> = inputs.first().map(|b| b.box_id().into());
+        let mints = minted_id
+            .and_then(|token_id| {
+                let (_, amount) = *totals.get(&token_id)?;
+                let first_holder = tx.outputs.iter().find(|b| {
+                    b.tokens
+                        .iter()
+                        .flat_map(|tokens| tokens.iter())
+                        .any(|t| String::from(t.token_id) == token_id)
+                })?;
+                let registers =
+                    decode_registers(&first_holder.additional_registers).unwrap_or_default();
+                Some(TokenEvent::TokenMinted(TokenMint {
+                    token_id,
+                    amount,
+                    name: eip4_string(&registers, "R4"),
+                    description: eip4_string(&registers, "R5"),
+                    decimals: eip4_string(&registers, "R6").and_then(|d| d.parse().ok()),
+                    box_id: first_holder.box_id().into(),
+                    context: context.clone(),
+                }))
+            })
+            .into_iter();
+        let transfers = changes.into_iter().filter(|(_, amount)| *amount != 0).map(
+            |((token_id, address), amount)| {
+                TokenEvent::TokenTransferred(TokenTransfer {
+                    token_id,
+                    address,
+                    amount,
+                    context: context.clone(),
+                })
+            },
+        );
+        let burns = totals
+            .into_iter()
+            .filter(|(_, (spent, created))| spent > created)
+            .map(|(token_id, (spent, created))| {
+                TokenEvent::TokenBurned(TokenBurn {
+                    token_id,
+                    amount: spent - created,
+                    context: context.clone(),
+                })
+            });
+        let events = mints.chain(transfers).chain(burns);
+        if applied {
+            events.collect()
+        } else {
+            let mut events: Vec<_> = events.map(TokenEvent::inverse).collect();
+            events.reverse();
+            events
+        }
+    }
+
+    fn inverse(self) -> Self {
+        match self {
+            TokenEvent::TokenMinted(m) => TokenEvent::TokenUnminted(m),
+            TokenEvent::TokenBurned(b) => TokenEvent::TokenUnburned(b),
+            TokenEvent::TokenTransferred(t) => TokenEvent::TokenUntransferred(t),
+            TokenEvent::TokenUnminted(m) => TokenEvent::TokenMinted(m),
+            TokenEvent::TokenUnburned(b) => TokenEvent::TokenBurned(b),
+            TokenEvent::TokenUntransferred(t) => TokenEvent::TokenTransferred(t),
+        }
+    }
+
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            TokenEvent::TokenMinted(_) => "TokenMinted",
+            TokenEvent::TokenBurned(_) => "TokenBurned",
+            TokenEvent::TokenTransferred(_) => "TokenTransferred",
+            TokenEvent::TokenUnminted(_) => "TokenUnminted",
+            TokenEvent::TokenUnburned(_) => "TokenUnburned",
+            TokenEvent::TokenUntransferred(_) => "TokenUntransferred",
+        }
+    }
+
+    pub fn token_id(&self) -> &str {
+        match self {
+            TokenEvent::TokenMinted(m) | TokenEvent::TokenUnminted(m) => &m.token_id,
+            TokenEvent::TokenBurned(b) | TokenEvent::TokenUnburned(b) => &b.token_id,
+            TokenEvent::TokenTransferred(t) | TokenEvent::TokenUntransferred(t) => &t.token_id,
+        }
+    }
+
+    pub fn context(&self) -> &TxContext {
+        match self {
+            TokenEvent::TokenMinted(m) | TokenEvent::TokenUnminted(m) => &m.context,
+            TokenEvent::TokenBurned(b) | TokenEvent::TokenUnburned(b) => &b.context,
+            TokenEvent::TokenTransferred(t) | TokenEvent::TokenUntransferred(t) => &t.context,
+        }
+    }
+
+    /// The event as JSON, with the payload under the event type.
+    pub fn to_json(&self) -> Value {
+        // Can't fail, since payloads consist of plain strings and numbers only.
+        let payload = match self {
+            TokenEvent::TokenMinted(m) | TokenEvent::TokenUnminted(m) => serde_json::to_value(m),
+            TokenEvent::TokenBurned(b) | TokenEvent::TokenUnburned(b) => serde_json::to_value(b),
+            TokenEvent::TokenTransferred(t) | TokenEvent::TokenUntransferred(t) => {
+                serde_json::to_value(t)
+            }
+        }
+        .unwrap();
+        let mut json = serde_json::Map::new();
+        json.insert(self.event_type().to_string(), payload);
+        Value::Object(json)
+    }
+}
+
+/// EIP-4 string stored in the given register as UTF-8 encoded `Coll[Byte]`.
+fn eip4_string(registers: &BTreeMap<String, DecodedRegister>, name: &str) -> Option<String> {
+    registers
+        .get(name)?
+        .value
+        .get("utf8")?
+        .as_str()
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_chain_sync::client::model::BlockTransaction;
+    use ergo_lib::ergotree_ir::chain::token::TokenId;
+    use serde_json::json;
+
+    use crate::models::address::{encode_address_or_tree, Network};
+    use crate::models::fixtures::{
+        applied, ergo_box, p2pk_tree, stamp, token_id, tx, unapplied, with_registers,
+    };
+    use crate::models::token_event::TokenEvent;
+    use crate::models::tx_event::TxEvent;
+
+    fn address(owner: usize) -> String {
+        encode_address_or_tree(&p2pk_tree(owner), Network::Mainnet)
+    }
+
+    /// Event type, token ID, address (of transfers) and amount of every event.
+    fn summary(ev: &TxEvent) -> Vec<(&'static str, String, Option<String>, i64)> {
+        TokenEvent::from_tx_event(ev, Network::Mainnet)
+            .into_iter()
+            .map(|ev| {
+                let event_type = ev.event_type();
+                match ev {
+                    TokenEvent::TokenMinted(m) | TokenEvent::TokenUnminted(m) => {
+                        (event_type, m.token_id, None, m.amount as i64)
+                    }
+                    TokenEvent::TokenBurned(b) | TokenEvent::TokenUnburned(b) => {
+                        (event_type, b.token_id, None, b.amount as i64)
+                    }
+                    TokenEvent::TokenTransferred(t) | TokenEvent::TokenUntransferred(t) => {
+                        (event_type, t.token_id, Some(t.address), t.amount)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Owner 0 mints a token with EIP-4 metadata to owner 1.
+    fn mint_tx() -> (BlockTransaction, TokenId) {
+        let input = stamp(ergo_box(0, 2_000_000, &[]), 1, 0);
+        let minted = TokenId::from(input.box_id());
+        let output = with_registers(
+            ergo_box(1, 2_000_000, &[(minted, 1000)]),
+            json!({"R4": "0e03616263", "R5": "0e0464657363", "R6": "0e0132"}),
+        );
+        (tx(2, vec![input], vec![output]), minted)
+    }
+
+    #[test]
+    fn token_with_the_first_input_id_is_minted() {
+        let (mint_tx, minted) = mint_tx();
+        let minted = String::from(minted);
+        assert_eq!(
+            summary(&applied(mint_tx.clone())),
+            vec![
+                ("TokenMinted", minted.clone(), None, 1000),
+                ("TokenTransferred", minted, Some(address(1)), 1000),
+            ]
+        );
+        match &TokenEvent::from_tx_event(&applied(mint_tx.clone()), Network::Mainnet)[0] {
+            TokenEvent::TokenMinted(m) => {
+                assert_eq!(m.name.as_deref(), Some("abc"));
+                assert_eq!(m.description.as_deref(), Some("desc"));
+                assert_eq!(m.decimals, Some(2));
+                assert_eq!(m.box_id, String::from(mint_tx.outputs.first().box_id()));
+            }
+            other => panic!("Expected a mint, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tokens_missing_from_outputs_are_burned() {
+        let burn_tx = tx(
+            2,
+            vec![stamp(ergo_box(0, 2_000_000, &[(token_id(7), 100)]), 1, 0)],
+            vec![ergo_box(0, 2_000_000, &[(token_id(7), 40)])],
+        );
+        let token = String::from(token_id(7));
+        assert_eq!(
+            summary(&applied(burn_tx)),
+            vec![
+                ("TokenTransferred", token.clone(), Some(address(0)), -60),
+                ("TokenBurned", token, None, 60),
+            ]
+        );
+    }
+
+    #[test]
+    fn transfers_are_net_changes_per_address() {
+        // Owner 0 sends a token to owner 1 in two boxes, while owner 2 keeps another token
+        // in a new box, which nets out to no transfer.
+        let transfer_tx = tx(
+            2,
+            vec![
+                stamp(ergo_box(0, 2_000_000, &[(token_id(7), 100)]), 1, 0),
+                stamp(ergo_box(2, 1_000_000, &[(token_id(8), 5)]), 1, 1),
+            ],
+            vec![
+                ergo_box(1, 1_000_000, &[(token_id(7), 60)]),
+                ergo_box(1, 1_000_000, &[(token_id(7), 40)]),
+                ergo_box(2, 1_000_000, &[(token_id(8), 5)]),
+            ],
+        );
+        let token = String::from(token_id(7));
+        let mut expected = vec![
+            ("TokenTransferred", token.clone(), Some(address(0)), -100),
+            ("TokenTransferred", token, Some(address(1)), 100),
+        ];
+        // Transfers of a token are ordered by address.
+        expected.sort();
+        assert_eq!(summary(&applied(transfer_tx)), expected);
+    }
+
+    #[test]
+    fn rolled_back_txs_yield_inverses_in_reverse_order() {
+        let (mint_tx, _) = mint_tx();
+        let burn_tx = tx(
+            3,
+            vec![stamp(ergo_box(0, 2_000_000, &[(token_id(7), 100)]), 1, 0)],
+            vec![ergo_box(1, 2_000_000, &[(token_id(7), 40)])],
+        );
+        for tx in [mint_tx, burn_tx] {
+            let inverses: Vec<_> = summary(&applied(tx.clone()))
+                .into_iter()
+                .rev()
+                .map(|(event_type, token_id, address, amount)| {
+                    let inverse = match event_type {
+                        "TokenMinted" => "TokenUnminted",
+                        "TokenBurned" => "TokenUnburned",
+                        "TokenTransferred" => "TokenUntransferred",
+                        other => panic!("Unexpected event {}", other),
+                    };
+                    (inverse, token_id, address, amount)
+                })
+                .collect();
+            assert_eq!(summary(&unapplied(tx)), inverses);
+        }
+    }
+}
@@ -1,4 +1,9 @@
+use std::collections::BTreeSet;
+
 use ergo_chain_sync::client::model::BlockTransaction;
+use serde::{Deserialize, Serialize};
+
+use crate::models::address::{encode_address, ergo_tree_hash, Network};
 
 /// Possible events that can happen with transactions on-chain.
 #[derive(Debug, Clone)]
@@ -16,3 +21,213 @@ pub enum TxEvent {
         block_id: String,
     },
 }
+
+impl TxEvent {
+    pub fn tx(&self) -> &BlockTransaction {
+        match self {
+            TxEvent::AppliedTx { tx, .. } | TxEvent::UnappliedTx { tx, .. } => tx,
+        }
+    }
+
+    pub fn map_tx(self, f: impl FnOnce(BlockTransaction) -> BlockTransaction) -> Self {
+        match self {
+            TxEvent::AppliedTx {
+                timestamp,
+                tx,
+                block_height,
+                block_id,
+            } => TxEvent::AppliedTx {
+                timestamp,
+                tx: f(tx),
+                block_height,
+                block_id,
+            },
+            TxEvent::UnappliedTx {
+                timestamp,
+                tx,
+                block_height,
+                block_id,
+            } => TxEvent::UnappliedTx {
+                timestamp,
+                tx: f(tx),
+                block_height,
+                block_id,
+            },
+        }
+    }
+
+    pub fn block_height(&self) -> i32 {
+        match self {
+            TxEvent::AppliedTx { block_height, .. } | TxEvent::UnappliedTx { block_height, .. } => {
+                *block_height
+            }
+        }
+    }
+
+    pub fn context(&self) -> TxContext {
+        match self {
+            TxEvent::AppliedTx {
+                timestamp,
+                tx,
+                block_height,
+                block_id,
+            }
+            | TxEvent::UnappliedTx {
+                timestamp,
+                tx,
+                block_height,
+                block_id,
+            } => TxContext {
+                tx_id: tx.id.into(),
+                timestamp: *timestamp,
+                height: *block_height,
+                block_id: block_id.clone(),
+            },
+        }
+    }
+
+    /// Partition keys of the event under the given strategy, derived from the boxes the tx
+    /// creates. An event is published once per key.
+    pub fn keys(&self, keying: TxKeying, network: Network) -> Vec<String> {
+        let tx = self.tx();
+        let keys: BTreeSet<String> = match keying {
+            TxKeying::TxId => BTreeSet::new(),
+            TxKeying::BlockHeight => BTreeSet::from([self.block_height().to_string()]),
+            TxKeying::ErgoTreeHash => tx
+                .outputs
+                .iter()
+                .filter_map(|b| ergo_tree_hash(&b.ergo_tree))
+                .collect(),
+            TxKeying::Address => tx
+                .outputs
+                .iter()
+                .filter_map(|b| encode_address(&b.ergo_tree, network))
+                .collect(),
+            TxKeying::TokenId => tx
+                .outputs
+                .iter()
+                .flat_map(|b| b.tokens.iter().flat_map(|tokens| tokens.iter()))
+                .map(|t| String::from(t.token_id))
+                .collect(),
+        };
+        if keys.is_empty() {
+            vec![tx.id.into()]
+        } else {
+            keys.into_iter().collect()
+        }
+    }
+}
+
+/// The tx an event derived from a [`TxEvent`] originates from.
+#[derive(Debug, Clone, Serialize)]
+pub struct TxContext {
+    pub tx_id: String,
+    pub timestamp: i64,
+    pub height: i32,
+    pub block_id: String,
+}
+
+/// How records of `tx_topic` are keyed. Consumers sharded by one of these keys receive all
+/// events relevant to their shard on a single partition, in chain order.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxKeying {
+    #[default]
+    TxId,
+    BlockHeight,
+    /// One record per distinct ErgoTree hash among created boxes.
+    ErgoTreeHash,
+    /// One record per distinct address among created boxes.
+    Address,
+    /// One record per distinct token among created boxes. Transactions creating no token
+    /// are keyed by tx id.
+    TokenId,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use ergo_chain_sync::client::model::BlockTransaction;
+
+    use crate::models::address::{encode_address, ergo_tree_hash, Network};
+    use crate::models::fixtures::{applied, ergo_box, p2pk_tree, stamp, token_id, tx, HEIGHT};
+    use crate::models::tx_event::TxKeying;
+
+    /// Owner 0 sends a token to owner 1 in two boxes and gets change, so that both owners and
+    /// the token appear several times.
+    fn transfer_tx() -> BlockTransaction {
+        let input = stamp(ergo_box(0, 3_000_000, &[(token_id(7), 100)]), 1, 0);
+        tx(
+            2,
+            vec![input],
+            vec![
+                ergo_box(1, 1_000_000, &[(token_id(7), 60)]),
+                ergo_box(1, 1_000_000, &[(token_id(7), 40)]),
+                ergo_box(0, 1_000_000, &[]),
+            ],
+        )
+    }
+
+    fn keys(keying: TxKeying) -> Vec<String> {
+        applied(transfer_tx()).keys(keying, Network::Mainnet)
+    }
+
+    #[test]
+    fn tx_id_keying() {
+        assert_eq!(keys(TxKeying::TxId), vec![String::from(transfer_tx().id)]);
+    }
+
+    #[test]
+    fn block_height_keying() {
+        assert_eq!(keys(TxKeying::BlockHeight), vec![HEIGHT.to_string()]);
+    }
+
+    #[test]
+    fn ergo_tree_hash_keying_has_one_key_per_tree() {
+        let expected: BTreeSet<String> = (0..2)
+            .filter_map(|owner| ergo_tree_hash(&p2pk_tree(owner)))
+            .collect();
+        assert_eq!(keys(TxKeying::ErgoTreeHash), Vec::from_iter(expected));
+    }
+
+    #[test]
+    fn address_keying_has_one_key_per_address() {
+        let expected: BTreeSet<String> = (0..2)
+            .filter_map(|owner| encode_address(&p2pk_tree(owner), Network::Mainnet))
+            .collect();
+        assert_eq!(keys(TxKeying::Address), Vec::from_iter(expected));
+    }
+
+    #[test]
+    fn token_id_keying_has_one_key_per_token() {
+        assert_eq!(keys(TxKeying::TokenId), vec![String::from(token_id(7))]);
+        // Txs without tokens fall back to the tx id.
+        let plain_tx = tx(
+            2,
+            vec![ergo_box(0, 2_000_000, &[])],
+            vec![ergo_box(1, 2_000_000, &[])],
+        );
+        assert_eq!(
+            applied(plain_tx.clone()).keys(TxKeying::TokenId, Network::Mainnet),
+            vec![String::from(plain_tx.id)]
+        );
+    }
+
+    #[test]
+    fn spent_boxes_are_not_keyed() {
+        // Owner 0 burns the token and sends everything to owner 1.
+        let input = stamp(ergo_box(0, 2_000_000, &[(token_id(7), 100)]), 1, 0);
+        let burn_tx = tx(2, vec![input], vec![ergo_box(1, 2_000_000, &[])]);
+        let burn_keys = |keying| applied(burn_tx.clone()).keys(keying, Network::Mainnet);
+        assert_eq!(
+            burn_keys(TxKeying::Address),
+            Vec::from_iter(encode_address(&p2pk_tree(1), Network::Mainnet))
+        );
+        assert_eq!(
+            burn_keys(TxKeying::ErgoTreeHash),
+            Vec::from_iter(ergo_tree_hash(&p2pk_tree(1)))
+        );
+        assert_eq!(burn_keys(TxKeying::TokenId), vec![String::from(burn_tx.id)]);
+    }
+}
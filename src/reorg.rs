@@ -0,0 +1,245 @@
+use std::mem;
+
+use ergo_chain_sync::model::Block;
+use ergo_chain_sync::ChainUpgrade;
+use ergo_lib::ergo_chain_types::BlockId;
+
+use crate::models::block_event::Reorg;
+
+/// Upgrades to pass downstream together, along with the reorg they make up, if any.
+pub type Segment = (Vec<ChainUpgrade>, Option<Reorg>);
+
+/// Groups the roll backwards of a reorg with the new branch fetched along with them, i.e. every
+/// roll forward following them in their batch. Roll backwards are held back until a batch brings
+/// the new branch, as rolling back might take several batches.
+#[derive(Default)]
+pub struct ReorgTracker {
+    pending: Option<PendingReorg>,
+}
+
+struct PendingReorg {
+    common_ancestor_id: BlockId,
+    common_ancestor_height: u32,
+    orphaned: Vec<BlockId>,
+    replacing: Vec<BlockId>,
+    upgrades: Vec<ChainUpgrade>,
+}
+
+impl PendingReorg {
+    /// Reorg starting with the roll backward of the given block.
+    fn new(blk: &Block) -> Self {
+        Self {
+            common_ancestor_id: blk.parent_id,
+            common_ancestor_height: blk.height.saturating_sub(1),
+            orphaned: vec![blk.id],
+            replacing: vec![],
+            upgrades: vec![],
+        }
+    }
+
+    fn complete(self) -> Segment {
+        let reorg = Reorg {
+            common_ancestor_id: hex(&self.common_ancestor_id),
+            common_ancestor_height: self.common_ancestor_height,
+            orphaned_block_ids: self.orphaned.iter().map(hex).collect(),
+            replacing_block_ids: self.replacing.iter().map(hex).collect(),
+        };
+        (self.upgrades, Some(reorg))
+    }
+}
+
+impl ReorgTracker {
+    /// Splits the given batch of upgrades into segments to pass downstream in order.
+    pub fn apply(&mut self, batch: Vec<ChainUpgrade>) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        let mut passed = Vec::new();
+        for upgrade in batch {
+            match (&mut self.pending, &upgrade) {
+                (None, ChainUpgrade::RollForward(_)) => passed.push(upgrade),
+                (None, ChainUpgrade::RollBackward(blk)) => {
+                    if !passed.is_empty() {
+                        segments.push((mem::take(&mut passed), None));
+                    }
+                    let mut pending = PendingReorg::new(blk);
+                    pending.upgrades.push(upgrade);
+                    self.pending = Some(pending);
+                }
+                (Some(pending), ChainUpgrade::RollBackward(blk))
+                    if pending.replacing.is_empty() =>
+                {
+                    // Every further roll backward makes the reorg deeper.
+                    pending.common_ancestor_id = blk.parent_id;
+                    pending.common_ancestor_height = blk.height.saturating_sub(1);
+                    pending.orphaned.push(blk.id);
+                    pending.upgrades.push(upgrade);
+                }
+                (Some(_), ChainUpgrade::RollBackward(blk)) => {
+                    // The new branch is being rolled back in turn, which starts another reorg.
+                    let mut next = PendingReorg::new(blk);
+                    next.upgrades.push(upgrade);
+                    segments.push(self.pending.replace(next).unwrap().complete());
+                }
+                (Some(pending), ChainUpgrade::RollForward(blk)) => {
+                    pending.replacing.push(blk.id);
+                    pending.upgrades.push(upgrade);
+                }
+            }
+        }
+        if !passed.is_empty() {
+            segments.push((passed, None));
+        }
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| !pending.replacing.is_empty())
+        {
+            segments.push(self.pending.take().unwrap().complete());
+        }
+        segments
+    }
+}
+
+fn hex(id: &BlockId) -> String {
+    base16::encode_lower(id.0 .0.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_chain_sync::model::Block;
+    use ergo_chain_sync::ChainUpgrade;
+    use ergo_lib::ergo_chain_types::{BlockId, Digest32};
+
+    use crate::reorg::{hex, ReorgTracker};
+
+    fn block(height: u32, fork: u8, parent_id: BlockId) -> Block {
+        Block {
+            id: BlockId(Digest32::from([height as u8 + fork; 32])),
+            parent_id,
+            height,
+            timestamp: 0,
+            transactions: vec![],
+            header: None,
+            size: None,
+        }
+    }
+
+    fn ids(upgrades: &[ChainUpgrade]) -> Vec<BlockId> {
+        upgrades
+            .iter()
+            .map(|u| match u {
+                ChainUpgrade::RollForward(blk) | ChainUpgrade::RollBackward(blk) => blk.id,
+            })
+            .collect()
+    }
+
+    /// Chain of blocks from height 1 up to `to_height`, forking off the chain at `fork_height`.
+    fn branch(to_height: u32, fork: u8, fork_height: u32) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for height in 1..=to_height {
+            let parent_id = blocks
+                .last()
+                .map_or(BlockId(Digest32::zero()), |blk| blk.id);
+            let fork = if height >= fork_height { fork } else { 0 };
+            blocks.push(block(height, fork, parent_id));
+        }
+        blocks
+    }
+
+    #[test]
+    fn reorg_spans_the_whole_new_branch_of_its_batch() {
+        let main = branch(3, 0, 0);
+        let fork = branch(4, 100, 2);
+        let mut tracker = ReorgTracker::default();
+
+        let batch = vec![
+            ChainUpgrade::RollBackward(main[2].clone()),
+            ChainUpgrade::RollBackward(main[1].clone()),
+            ChainUpgrade::RollForward(fork[1].clone()),
+            ChainUpgrade::RollForward(fork[2].clone()),
+            ChainUpgrade::RollForward(fork[3].clone()),
+        ];
+        let mut segments = tracker.apply(batch.clone());
+        assert_eq!(segments.len(), 1);
+        let (upgrades, reorg) = segments.remove(0);
+        assert_eq!(ids(&upgrades), ids(&batch));
+        let reorg = reorg.unwrap();
+        assert_eq!(reorg.common_ancestor_id, hex(&main[0].id));
+        assert_eq!(reorg.common_ancestor_height, 1);
+        assert_eq!(
+            reorg.orphaned_block_ids,
+            vec![hex(&main[2].id), hex(&main[1].id)]
+        );
+        assert_eq!(
+            reorg.replacing_block_ids,
+            vec![hex(&fork[1].id), hex(&fork[2].id), hex(&fork[3].id)]
+        );
+
+        // Later blocks of the new branch are passed on right away.
+        let fork_tip = block(5, 100, fork[3].id);
+        let segments = tracker.apply(vec![ChainUpgrade::RollForward(fork_tip.clone())]);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(ids(&segments[0].0), vec![fork_tip.id]);
+        assert!(segments[0].1.is_none());
+    }
+
+    #[test]
+    fn reorg_completes_on_a_new_branch_shorter_than_the_orphaned_one() {
+        let main = branch(3, 0, 0);
+        let fork = branch(2, 100, 2);
+        let mut tracker = ReorgTracker::default();
+
+        let segments = tracker.apply(vec![
+            ChainUpgrade::RollBackward(main[2].clone()),
+            ChainUpgrade::RollBackward(main[1].clone()),
+            ChainUpgrade::RollForward(fork[1].clone()),
+        ]);
+        assert_eq!(segments.len(), 1);
+        let reorg = segments[0].1.clone().unwrap();
+        assert_eq!(
+            reorg.orphaned_block_ids,
+            vec![hex(&main[2].id), hex(&main[1].id)]
+        );
+        assert_eq!(reorg.replacing_block_ids, vec![hex(&fork[1].id)]);
+    }
+
+    #[test]
+    fn roll_backwards_are_held_back_until_a_batch_brings_the_new_branch() {
+        let main = branch(4, 0, 0);
+        let fork = branch(4, 100, 3);
+        let mut tracker = ReorgTracker::default();
+
+        // Blocks preceding the reorg in its batch are passed on by themselves.
+        let segments = tracker.apply(vec![
+            ChainUpgrade::RollForward(main[3].clone()),
+            ChainUpgrade::RollBackward(main[3].clone()),
+        ]);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(ids(&segments[0].0), vec![main[3].id]);
+        assert!(segments[0].1.is_none());
+
+        let segments = tracker.apply(vec![ChainUpgrade::RollBackward(main[2].clone())]);
+        assert!(segments.is_empty());
+
+        let segments = tracker.apply(vec![
+            ChainUpgrade::RollForward(fork[2].clone()),
+            ChainUpgrade::RollForward(fork[3].clone()),
+        ]);
+        assert_eq!(segments.len(), 1);
+        let (upgrades, reorg) = &segments[0];
+        assert_eq!(
+            ids(upgrades),
+            vec![main[3].id, main[2].id, fork[2].id, fork[3].id]
+        );
+        let reorg = reorg.clone().unwrap();
+        assert_eq!(reorg.common_ancestor_id, hex(&main[1].id));
+        assert_eq!(reorg.common_ancestor_height, 2);
+        assert_eq!(
+            reorg.orphaned_block_ids,
+            vec![hex(&main[3].id), hex(&main[2].id)]
+        );
+        assert_eq!(
+            reorg.replacing_block_ids,
+            vec![hex(&fork[2].id), hex(&fork[3].id)]
+        );
+    }
+}
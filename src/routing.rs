@@ -0,0 +1,244 @@
+use std::collections::HashSet;
+
+use ergo_chain_sync::client::model::BlockTransaction;
+use serde::Deserialize;
+
+use crate::models::address::{encode_address, ergo_tree_template_hash, Network};
+
+/// Republishes txs touching any of the given contracts, addresses or tokens to dedicated
+/// topics. A tx matches if any of its inputs or outputs matches any of the criteria.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Route {
+    /// Topic matching applied and unapplied txs are republished to.
+    pub topic: Option<String>,
+    /// Topic matching mempool events are republished to.
+    pub mempool_topic: Option<String>,
+    /// Hex-encoded hashes of ErgoTree templates.
+    #[serde(default)]
+    pub ergo_tree_template_hashes: HashSet<String>,
+    /// Base58-encoded addresses.
+    #[serde(default)]
+    pub addresses: HashSet<String>,
+    /// Hex-encoded token IDs.
+    #[serde(default)]
+    pub token_ids: HashSet<String>,
+}
+
+/// Everything of a tx routes can match against.
+struct TxFootprint {
+    ergo_tree_template_hashes: HashSet<String>,
+    addresses: HashSet<String>,
+    token_ids: HashSet<String>,
+}
+
+impl TxFootprint {
+    fn new(tx: &BlockTransaction, network: Network) -> Self {
+        let boxes = || {
+            tx.inputs
+                .iter()
+                .map(|input| &input.ergo_box)
+                .chain(tx.outputs.iter())
+        };
+        Self {
+            ergo_tree_template_hashes: boxes()
+                .filter_map(|b| ergo_tree_template_hash(&b.ergo_tree))
+                .collect(),
+            addresses: boxes()
+                .filter_map(|b| encode_address(&b.ergo_tree, network))
+                .collect(),
+            token_ids: boxes()
+                .flat_map(|b| b.tokens.iter().flat_map(|tokens| tokens.iter()))
+                .map(|t| String::from(t.token_id))
+                .collect(),
+        }
+    }
+}
+
+impl Route {
+    fn matches(&self, footprint: &TxFootprint) -> bool {
+        !self
+            .ergo_tree_template_hashes
+            .is_disjoint(&footprint.ergo_tree_template_hashes)
+            || !self.addresses.is_disjoint(&footprint.addresses)
+            || !self.token_ids.is_disjoint(&footprint.token_ids)
+    }
+}
+
+/// Picks the topics a tx is republished to.
+#[derive(Debug, Clone)]
+pub struct Router {
+    routes: Vec<Route>,
+    network: Network,
+}
+
+impl Router {
+    pub fn new(routes: Vec<Route>, network: Network) -> Self {
+        // Hex strings are compared in lower case, the way they are encoded.
+        let routes = routes
+            .into_iter()
+            .map(|route| Route {
+                ergo_tree_template_hashes: lowercase(route.ergo_tree_template_hashes),
+                token_ids: lowercase(route.token_ids),
+                ..route
+            })
+            .collect();
+        Self { routes, network }
+    }
+
+    /// Topics of all routes matching the given applied or unapplied tx.
+    pub fn tx_topics(&self, tx: &BlockTransaction) -> Vec<String> {
+        self.matching_topics(tx, |route| route.topic.as_ref())
+    }
+
+    /// Mempool topics of all routes matching the given mempool tx.
+    pub fn mempool_topics(&self, tx: &BlockTransaction) -> Vec<String> {
+        self.matching_topics(tx, |route| route.mempool_topic.as_ref())
+    }
+
+    /// Topics of all routes, as given by `select`.
+    pub fn topics(&self, select: impl Fn(&Route) -> Option<&String>) -> HashSet<String> {
+        self.routes
+            .iter()
+            .filter_map(|route| select(route).cloned())
+            .collect()
+    }
+
+    fn matching_topics(
+        &self,
+        tx: &BlockTransaction,
+        select: impl Fn(&Route) -> Option<&String>,
+    ) -> Vec<String> {
+        let mut routes = self
+            .routes
+            .iter()
+            .filter(|route| select(route).is_some())
+            .peekable();
+        if routes.peek().is_none() {
+            return vec![];
+        }
+        let footprint = TxFootprint::new(tx, self.network);
+        let mut topics = Vec::new();
+        for route in routes.filter(|route| route.matches(&footprint)) {
+            let topic = select(route).unwrap();
+            // Several routes may share a topic, the tx is still published there once.
+            if !topics.contains(topic) {
+                topics.push(topic.clone());
+            }
+        }
+        topics
+    }
+}
+
+fn lowercase(hexes: HashSet<String>) -> HashSet<String> {
+    hexes.into_iter().map(|hex| hex.to_lowercase()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use ergo_chain_sync::client::model::BlockTransaction;
+
+    use crate::models::address::{encode_address, ergo_tree_template_hash, Network};
+    use crate::models::fixtures::{ergo_box, p2pk_tree, stamp, token_id, tx};
+    use crate::routing::{Route, Router};
+
+    fn route(topic: Option<&str>, mempool_topic: Option<&str>) -> Route {
+        Route {
+            topic: topic.map(str::to_string),
+            mempool_topic: mempool_topic.map(str::to_string),
+            ergo_tree_template_hashes: HashSet::new(),
+            addresses: HashSet::new(),
+            token_ids: HashSet::new(),
+        }
+    }
+
+    fn address(owner: usize) -> String {
+        encode_address(&p2pk_tree(owner), Network::Mainnet).unwrap()
+    }
+
+    /// Owner 0 sends a token to owner 1.
+    fn transfer_tx() -> BlockTransaction {
+        tx(
+            2,
+            vec![stamp(ergo_box(0, 2_000_000, &[(token_id(7), 1)]), 1, 0)],
+            vec![ergo_box(1, 2_000_000, &[(token_id(7), 1)])],
+        )
+    }
+
+    #[test]
+    fn txs_are_routed_by_template_address_and_token() {
+        let template_hash = ergo_tree_template_hash(&p2pk_tree(1)).unwrap();
+        let routes = vec![
+            // Hex-encoded criteria match regardless of case.
+            Route {
+                ergo_tree_template_hashes: HashSet::from([template_hash.to_uppercase()]),
+                ..route(Some("by_template"), Some("mempool_by_template"))
+            },
+            Route {
+                addresses: HashSet::from([address(0)]),
+                ..route(Some("by_address"), Some("mempool_by_address"))
+            },
+            Route {
+                token_ids: HashSet::from([String::from(token_id(7)).to_uppercase()]),
+                ..route(Some("by_token"), Some("mempool_by_token"))
+            },
+            Route {
+                ergo_tree_template_hashes: HashSet::from(["00".repeat(32)]),
+                addresses: HashSet::from([address(2)]),
+                token_ids: HashSet::from([String::from(token_id(8))]),
+                ..route(Some("unmatched"), Some("mempool_unmatched"))
+            },
+        ];
+        let router = Router::new(routes, Network::Mainnet);
+        assert_eq!(
+            router.tx_topics(&transfer_tx()),
+            vec!["by_template", "by_address", "by_token"]
+        );
+        assert_eq!(
+            router.mempool_topics(&transfer_tx()),
+            vec![
+                "mempool_by_template",
+                "mempool_by_address",
+                "mempool_by_token"
+            ]
+        );
+    }
+
+    #[test]
+    fn txs_are_published_once_per_topic() {
+        let routes = vec![
+            Route {
+                addresses: HashSet::from([address(0)]),
+                ..route(Some("shared"), Some("mempool_shared"))
+            },
+            Route {
+                token_ids: HashSet::from([String::from(token_id(7))]),
+                ..route(Some("shared"), Some("mempool_shared"))
+            },
+        ];
+        let router = Router::new(routes, Network::Mainnet);
+        assert_eq!(router.tx_topics(&transfer_tx()), vec!["shared"]);
+        assert_eq!(
+            router.mempool_topics(&transfer_tx()),
+            vec!["mempool_shared"]
+        );
+    }
+
+    #[test]
+    fn routes_without_a_topic_are_skipped() {
+        let routes = vec![
+            Route {
+                addresses: HashSet::from([address(0)]),
+                ..route(Some("confirmed_only"), None)
+            },
+            Route {
+                addresses: HashSet::from([address(0)]),
+                ..route(None, Some("mempool_only"))
+            },
+        ];
+        let router = Router::new(routes, Network::Mainnet);
+        assert_eq!(router.tx_topics(&transfer_tx()), vec!["confirmed_only"]);
+        assert_eq!(router.mempool_topics(&transfer_tx()), vec!["mempool_only"]);
+    }
+}
@@ -0,0 +1,224 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use derive_more::From;
+use log::error;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use wasm_timer::Delay;
+
+use crate::sink::kafka::{KafkaConfig, KafkaSink};
+use crate::sink::memory::InMemorySink;
+use crate::sink::sequencer::Sequencer;
+use crate::sink::stdout::StdoutSink;
+
+pub mod kafka;
+pub mod memory;
+pub mod outbox;
+pub mod sequencer;
+pub mod stdout;
+
+/// Version of the layout of record values. Bumped whenever it changes in a way consumers
+/// have to account for.
+///
+/// Version 2 added the address, ErgoTree template hash and decoded registers of boxes.
+pub const SCHEMA_VERSION: u32 = 2;
+
+pub const EVENT_TYPE_HEADER: &str = "event_type";
+pub const HEIGHT_HEADER: &str = "height";
+pub const BLOCK_ID_HEADER: &str = "block_id";
+pub const SCHEMA_VERSION_HEADER: &str = "schema_version";
+pub const SEQ_HEADER: &str = "seq";
+
+/// A single keyed message destined for a topic.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SinkRecord {
+    pub topic: String,
+    pub key: String,
+    /// `None` for tombstones, which delete the key from compacted topics.
+    pub value: Option<Vec<u8>>,
+    /// Metadata which lets consumers route records without parsing their values.
+    pub headers: Vec<(String, String)>,
+}
+
+impl SinkRecord {
+    pub fn new(
+        topic: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Self {
+        Self {
+            topic: topic.into(),
+            key: key.into(),
+            value: Some(value.into()),
+            headers: vec![(
+                SCHEMA_VERSION_HEADER.to_string(),
+                SCHEMA_VERSION.to_string(),
+            )],
+        }
+    }
+
+    pub fn tombstone(topic: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            value: None,
+            ..Self::new(topic, key, vec![])
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl ToString) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Error, From, Debug)]
+pub enum Error {
+    #[error("kafka: {0}")]
+    Kafka(rdkafka::error::KafkaError),
+    #[error("delivery canceled")]
+    Canceled(futures::channel::oneshot::Canceled),
+    #[error("rocksdb: {0}")]
+    RocksDb(rocksdb::Error),
+    #[error("io: {0}")]
+    Io(std::io::Error),
+    #[error("json encoding: {0}")]
+    Json(serde_json::Error),
+    /// An outbox record below the tail of its queue is absent, see [`outbox::Outbox`].
+    #[error("outbox record {0} is missing")]
+    #[from(ignore)]
+    MissingOutboxRecord(String),
+}
+
+/// Destination for all events produced by the streamer.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    /// Publishes the given record. Resolves once the record is acknowledged by the sink.
+    async fn send(&self, record: SinkRecord) -> Result<(), Error>;
+
+    /// Publishes the given records in order. Resolves once all of them are acknowledged,
+    /// or with the records that might not have been delivered.
+    async fn send_batch(&self, records: Vec<SinkRecord>) -> Result<(), BatchError> {
+        for (ix, rec) in records.iter().enumerate() {
+            if let Err(error) = self.send(rec.clone()).await {
+                return Err(BatchError::new(error, records, ix));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Failure to publish a batch. All records preceding `undelivered` were acknowledged.
+#[derive(Debug)]
+pub struct BatchError {
+    pub error: Error,
+    pub undelivered: Vec<SinkRecord>,
+}
+
+impl BatchError {
+    pub fn new(error: Error, mut records: Vec<SinkRecord>, failed_at: usize) -> Self {
+        Self {
+            error,
+            undelivered: records.split_off(failed_at),
+        }
+    }
+}
+
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Sends the given record, retrying until the sink acknowledges it.
+/// Later records must never overtake a failed one, so the caller is suspended meanwhile.
+pub async fn send_until_acked(sink: &dyn EventSink, record: SinkRecord) {
+    loop {
+        match sink.send(record.clone()).await {
+            Ok(()) => return,
+            Err(e) => {
+                error!(
+                    target: "sink",
+                    "Failed to publish record [{}] to [{}]: {}. Retrying in {:?}",
+                    record.key,
+                    record.topic,
+                    e,
+                    RETRY_DELAY
+                );
+                let _ = Delay::new(RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Sends the given records, retrying the undelivered ones until the sink acknowledges them.
+pub async fn send_batch_until_acked(sink: &dyn EventSink, records: Vec<SinkRecord>) {
+    let mut records = records;
+    while let Err(BatchError { error, undelivered }) = sink.send_batch(records).await {
+        error!(
+            target: "sink",
+            "Failed to publish {} records: {}. Retrying in {:?}",
+            undelivered.len(),
+            error,
+            RETRY_DELAY
+        );
+        let _ = Delay::new(RETRY_DELAY).await;
+        records = undelivered;
+    }
+}
+
+/// Records produced while handling the events of a single chain upgrade. They are published
+/// together once all events of the upgrade are handled.
+#[derive(Clone)]
+pub struct RecordBatch {
+    sink: Arc<dyn EventSink>,
+    sequencer: Arc<dyn Sequencer>,
+    records: Arc<Mutex<Vec<SinkRecord>>>,
+}
+
+impl RecordBatch {
+    pub fn new(sink: Arc<dyn EventSink>, sequencer: Arc<dyn Sequencer>) -> Self {
+        Self {
+            sink,
+            sequencer,
+            records: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn push(&self, record: SinkRecord) {
+        self.records.lock().unwrap().push(record);
+    }
+
+    /// Publishes all collected records, resolving once they are acknowledged.
+    pub async fn flush(&self) {
+        let mut records = mem::take(&mut *self.records.lock().unwrap());
+        if !records.is_empty() {
+            self.sequencer.stamp(&mut records).await;
+            send_batch_until_acked(&*self.sink, records).await;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// Publish to the Kafka cluster described by the `kafka` section.
+    #[default]
+    Kafka,
+    /// Write records to stdout as newline-delimited JSON.
+    Stdout,
+    /// Keep records in memory. Useful for tests and local debugging only.
+    InMemory,
+}
+
+pub fn make_sink(conf: &SinkConfig, kafka_conf: &KafkaConfig) -> Arc<dyn EventSink> {
+    match conf {
+        SinkConfig::Kafka => Arc::new(KafkaSink::new(kafka_conf)),
+        SinkConfig::Stdout => Arc::new(StdoutSink),
+        SinkConfig::InMemory => Arc::new(InMemorySink::new()),
+    }
+}
@@ -0,0 +1,251 @@
+use async_trait::async_trait;
+use futures::future::join_all;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::ClientConfig;
+use serde::Deserialize;
+
+use crate::sink::{BatchError, Error, EventSink, SinkRecord};
+
+pub mod topics;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Snappy => "snappy",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityProtocol {
+    #[default]
+    Plaintext,
+    Ssl,
+    SaslPlaintext,
+    SaslSsl,
+}
+
+impl SecurityProtocol {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SecurityProtocol::Plaintext => "plaintext",
+            SecurityProtocol::Ssl => "ssl",
+            SecurityProtocol::SaslPlaintext => "sasl_plaintext",
+            SecurityProtocol::SaslSsl => "sasl_ssl",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SaslMechanism {
+    Plain,
+    ScramSha256,
+    ScramSha512,
+}
+
+impl SaslMechanism {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            SaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SaslConfig {
+    pub mechanism: SaslMechanism,
+    pub username: String,
+    pub password: String,
+}
+
+/// Paths to PEM-encoded files.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SslConfig {
+    pub ca_location: Option<String>,
+    pub certificate_location: Option<String>,
+    pub key_location: Option<String>,
+    pub key_password: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Acks {
+    None,
+    #[default]
+    Leader,
+    All,
+}
+
+impl Acks {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Acks::None => "0",
+            Acks::Leader => "1",
+            Acks::All => "all",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct KafkaProducerConfig {
+    /// How long to wait for more records before sending out a batch.
+    pub linger_ms: u64,
+    /// Maximum size of a batch in bytes.
+    pub batch_size: usize,
+    /// Maximum number of records in a batch.
+    pub batch_num_messages: usize,
+    #[serde(default)]
+    pub compression: Compression,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct KafkaConfig {
+    /// Bootstrap brokers, each given as "host:port".
+    pub brokers: Vec<String>,
+    pub client_id: String,
+    #[serde(default)]
+    pub security_protocol: SecurityProtocol,
+    pub sasl: Option<SaslConfig>,
+    pub ssl: Option<SslConfig>,
+    #[serde(default)]
+    pub acks: Acks,
+    /// How long the broker may take to acknowledge a request.
+    pub request_timeout_ms: u64,
+    /// Upper bound on the time a record may take to be delivered, retries included.
+    pub message_timeout_ms: u64,
+    pub producer: KafkaProducerConfig,
+}
+
+impl KafkaConfig {
+    /// Connection and security settings shared by every Kafka client.
+    pub fn client_config(&self) -> ClientConfig {
+        let mut conf = ClientConfig::new();
+        conf.set("bootstrap.servers", self.brokers.join(","))
+            .set("client.id", &self.client_id)
+            .set("security.protocol", self.security_protocol.as_str());
+        if let Some(sasl) = &self.sasl {
+            conf.set("sasl.mechanism", sasl.mechanism.as_str())
+                .set("sasl.username", &sasl.username)
+                .set("sasl.password", &sasl.password);
+        }
+        if let Some(ssl) = &self.ssl {
+            for (key, value) in [
+                ("ssl.ca.location", &ssl.ca_location),
+                ("ssl.certificate.location", &ssl.certificate_location),
+                ("ssl.key.location", &ssl.key_location),
+                ("ssl.key.password", &ssl.key_password),
+            ] {
+                if let Some(value) = value {
+                    conf.set(key, value);
+                }
+            }
+        }
+        conf
+    }
+
+    fn producer_config(&self) -> ClientConfig {
+        let mut conf = self.client_config();
+        conf.set("acks", self.acks.as_str())
+            .set("request.timeout.ms", self.request_timeout_ms.to_string())
+            .set("message.timeout.ms", self.message_timeout_ms.to_string())
+            .set("linger.ms", self.producer.linger_ms.to_string())
+            .set("batch.size", self.producer.batch_size.to_string())
+            .set(
+                "batch.num.messages",
+                self.producer.batch_num_messages.to_string(),
+            )
+            .set("compression.type", self.producer.compression.as_str());
+        match self.acks {
+            // Idempotence keeps records of a partition in order across retries.
+            Acks::All => conf.set("enable.idempotence", "true"),
+            // Otherwise only a single request in flight does.
+            Acks::None | Acks::Leader => conf.set("max.in.flight.requests.per.connection", "1"),
+        };
+        conf
+    }
+}
+
+pub struct KafkaSink {
+    producer: FutureProducer,
+}
+
+impl KafkaSink {
+    pub fn new(conf: &KafkaConfig) -> Self {
+        Self {
+            producer: conf.producer_config().create().unwrap(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for KafkaSink {
+    async fn send(&self, record: SinkRecord) -> Result<(), Error> {
+        self.send_batch(vec![record]).await.map_err(|e| e.error)
+    }
+
+    /// All records are enqueued at once and acknowledged together, so the producer is free to
+    /// pack them into a single request per partition.
+    async fn send_batch(&self, records: Vec<SinkRecord>) -> Result<(), BatchError> {
+        let mut deliveries = Vec::with_capacity(records.len());
+        for (ix, rec) in records.iter().enumerate() {
+            let headers = rec
+                .headers
+                .iter()
+                .fold(OwnedHeaders::new(), |headers, (key, value)| {
+                    headers.insert(Header {
+                        key,
+                        value: Some(value.as_str()),
+                    })
+                });
+            let mut future_rec = FutureRecord::to(&rec.topic).key(&rec.key).headers(headers);
+            if let Some(value) = &rec.value {
+                future_rec = future_rec.payload(value);
+            }
+            match self.producer.send_result(future_rec) {
+                Ok(delivery) => deliveries.push(delivery),
+                Err((e, _)) => {
+                    // Records enqueued so far can still fail, so wait for them first.
+                    let acked = join_all(deliveries).await;
+                    let failed_at = first_failure(&acked).unwrap_or(ix);
+                    return Err(BatchError::new(Error::from(e), records, failed_at));
+                }
+            }
+        }
+        let acked = join_all(deliveries).await;
+        match first_failure(&acked) {
+            Some(failed_at) => {
+                let error = match &acked[failed_at] {
+                    Ok(Err((e, _))) => Error::from(e.clone()),
+                    Err(canceled) => Error::from(*canceled),
+                    Ok(Ok(_)) => unreachable!(),
+                };
+                Err(BatchError::new(error, records, failed_at))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+fn first_failure<T, E1, E2>(acked: &[Result<Result<T, E1>, E2>]) -> Option<usize> {
+    acked.iter().position(|res| !matches!(res, Ok(Ok(_))))
+}
@@ -0,0 +1,257 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::task::spawn_blocking;
+use derive_more::From;
+use log::info;
+use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, TopicReplication};
+use rdkafka::client::DefaultClientContext;
+use rdkafka::error::KafkaError;
+use rdkafka::types::RDKafkaErrorCode;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::models::tx_event::TxKeying;
+use crate::sink::kafka::KafkaConfig;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupPolicy {
+    Delete,
+    Compact,
+    CompactDelete,
+}
+
+impl CleanupPolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CleanupPolicy::Delete => "delete",
+            CleanupPolicy::Compact => "compact",
+            CleanupPolicy::CompactDelete => "compact,delete",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopicSettings {
+    pub partitions: i32,
+    pub replication_factor: i32,
+    /// Retention of records in milliseconds, `-1` meaning forever. Broker default if not set.
+    pub retention_ms: Option<i64>,
+    /// Broker default if not set.
+    pub cleanup_policy: Option<CleanupPolicy>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopicsConfig {
+    /// Whether missing topics are created at startup. Existing topics are validated regardless.
+    pub create_missing: bool,
+    pub timeout_ms: u64,
+    pub blocks: TopicSettings,
+    pub tx: TopicSettings,
+    pub mempool: TopicSettings,
+    pub dead_letter: TopicSettings,
+    /// Required if `headers_topic` is set.
+    #[serde(default)]
+    pub headers: Option<TopicSettings>,
+    /// Required if `boxes_topic` is set.
+    #[serde(default)]
+    pub boxes: Option<TopicSettings>,
+    /// Required if `tokens_topic` is set.
+    #[serde(default)]
+    pub tokens: Option<TopicSettings>,
+    /// Required if `address_deltas_topic` is set.
+    #[serde(default)]
+    pub address_deltas: Option<TopicSettings>,
+}
+
+/// How records of a topic are keyed, as far as partitioning is concerned.
+#[derive(Debug, Clone, Copy)]
+pub enum Keying {
+    /// Consumers rely on the order of all records of the topic.
+    Total,
+    /// Consumers rely on the order of records sharing a key.
+    PerKey,
+}
+
+impl From<TxKeying> for Keying {
+    /// Every strategy keeps the records of a key in order, e.g. the records of a block when
+    /// keyed by height.
+    fn from(tx_keying: TxKeying) -> Self {
+        match tx_keying {
+            TxKeying::TxId
+            | TxKeying::BlockHeight
+            | TxKeying::ErgoTreeHash
+            | TxKeying::Address
+            | TxKeying::TokenId => Keying::PerKey,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TopicSpec {
+    pub name: String,
+    pub keying: Keying,
+    pub settings: TopicSettings,
+}
+
+#[derive(Error, From, Debug)]
+pub enum Error {
+    #[error("kafka: {0}")]
+    Kafka(KafkaError),
+    #[error("failed to create topic [{0}]: {1}")]
+    #[from(ignore)]
+    CreateTopic(String, RDKafkaErrorCode),
+    #[error("topic [{0}] does not exist")]
+    #[from(ignore)]
+    MissingTopic(String),
+    #[error("topic [{topic}] has {actual} partitions, {expected} expected. Changing partition count would remap existing keys")]
+    #[from(ignore)]
+    PartitionCountMismatch {
+        topic: String,
+        expected: i32,
+        actual: i32,
+    },
+    #[error("topic [{0}] must have a single partition, since consumers rely on the order of all its records")]
+    #[from(ignore)]
+    NotSinglePartition(String),
+}
+
+/// Makes sure all the given topics exist and that their partitioning is compatible with
+/// the way their records are keyed.
+pub async fn provision_topics(
+    kafka_conf: &KafkaConfig,
+    conf: &TopicsConfig,
+    topics: Vec<TopicSpec>,
+) -> Result<(), Error> {
+    for spec in &topics {
+        check_partitioning(spec, spec.settings.partitions)?;
+    }
+
+    let admin: Arc<AdminClient<DefaultClientContext>> =
+        Arc::new(kafka_conf.client_config().create()?);
+    let timeout = Duration::from_millis(conf.timeout_ms);
+
+    let metadata = {
+        let admin = admin.clone();
+        spawn_blocking(move || admin.inner().fetch_metadata(None, timeout)).await?
+    };
+
+    let mut missing = Vec::new();
+    for spec in &topics {
+        match metadata.topics().iter().find(|t| t.name() == spec.name) {
+            Some(existing) => {
+                let actual = existing.partitions().len() as i32;
+                check_partitioning(spec, actual)?;
+                info!("Topic [{}] exists with {} partitions", spec.name, actual);
+            }
+            None if conf.create_missing => missing.push(spec),
+            None => return Err(Error::MissingTopic(spec.name.clone())),
+        }
+    }
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let retention: Vec<Option<String>> = missing
+        .iter()
+        .map(|spec| spec.settings.retention_ms.map(|ms| ms.to_string()))
+        .collect();
+    let new_topics: Vec<NewTopic> = missing
+        .iter()
+        .zip(&retention)
+        .map(|(spec, retention_ms)| {
+            let mut topic = NewTopic::new(
+                &spec.name,
+                spec.settings.partitions,
+                TopicReplication::Fixed(spec.settings.replication_factor),
+            );
+            if let Some(retention_ms) = retention_ms {
+                topic = topic.set("retention.ms", retention_ms);
+            }
+            if let Some(policy) = spec.settings.cleanup_policy {
+                topic = topic.set("cleanup.policy", policy.as_str());
+            }
+            topic
+        })
+        .collect();
+    let opts = AdminOptions::new().operation_timeout(Some(timeout));
+    for res in admin.create_topics(&new_topics, &opts).await? {
+        match res {
+            Ok(name) => info!("Created topic [{}]", name),
+            // Another instance might have created it in the meantime.
+            Err((_, RDKafkaErrorCode::TopicAlreadyExists)) => {}
+            Err((name, code)) => return Err(Error::CreateTopic(name, code)),
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the given number of partitions is compatible with the way records of the topic
+/// are keyed.
+fn check_partitioning(spec: &TopicSpec, partitions: i32) -> Result<(), Error> {
+    match spec.keying {
+        Keying::Total if partitions != 1 => Err(Error::NotSinglePartition(spec.name.clone())),
+        Keying::PerKey if partitions != spec.settings.partitions => {
+            Err(Error::PartitionCountMismatch {
+                topic: spec.name.clone(),
+                expected: spec.settings.partitions,
+                actual: partitions,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::tx_event::TxKeying;
+    use crate::sink::kafka::topics::{check_partitioning, Error, Keying, TopicSettings, TopicSpec};
+
+    fn topic(keying: Keying, partitions: i32) -> TopicSpec {
+        TopicSpec {
+            name: "topic".to_string(),
+            keying,
+            settings: TopicSettings {
+                partitions,
+                replication_factor: 1,
+                retention_ms: None,
+                cleanup_policy: None,
+            },
+        }
+    }
+
+    #[test]
+    fn total_order_requires_single_partition() {
+        let spec = topic(Keying::Total, 4);
+        assert!(matches!(
+            check_partitioning(&spec, 4),
+            Err(Error::NotSinglePartition(_))
+        ));
+        let spec = topic(Keying::Total, 1);
+        assert!(check_partitioning(&spec, 1).is_ok());
+    }
+
+    #[test]
+    fn per_key_keying_requires_configured_partition_count() {
+        for tx_keying in [
+            TxKeying::TxId,
+            TxKeying::BlockHeight,
+            TxKeying::ErgoTreeHash,
+            TxKeying::Address,
+            TxKeying::TokenId,
+        ] {
+            let spec = topic(tx_keying.into(), 4);
+            assert!(check_partitioning(&spec, 4).is_ok());
+            assert!(matches!(
+                check_partitioning(&spec, 2),
+                Err(Error::PartitionCountMismatch {
+                    expected: 4,
+                    actual: 2,
+                    ..
+                })
+            ));
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::sink::{Error, EventSink, SinkRecord};
+
+/// Accumulates records in memory, in the order they were sent.
+#[derive(Clone, Default)]
+pub struct InMemorySink {
+    records: Arc<Mutex<Vec<SinkRecord>>>,
+}
+
+impl InMemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Snapshot of all records sent so far.
+    pub fn records(&self) -> Vec<SinkRecord> {
+        self.records.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl EventSink for InMemorySink {
+    async fn send(&self, record: SinkRecord) -> Result<(), Error> {
+        self.records.lock().unwrap().push(record);
+        Ok(())
+    }
+}
@@ -0,0 +1,508 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_std::task::spawn_blocking;
+use async_trait::async_trait;
+use futures::{stream, Stream};
+use log::{info, warn};
+use serde::Deserialize;
+use tokio::sync::Mutex as AsyncMutex;
+use wasm_timer::Delay;
+
+use crate::sink::{BatchError, Error, EventSink, SinkRecord};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutboxConfig {
+    pub db_path: String,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// How often the outbox depth is reported, even while it is 0. Every minute if unset.
+    #[serde(default)]
+    pub depth_report_interval_secs: Option<u64>,
+}
+
+/// Sequence numbers of the records queued for a single topic, `head` being the oldest one.
+#[derive(Debug, Clone, Copy, Default)]
+struct Queue {
+    head: u64,
+    tail: u64,
+}
+
+impl Queue {
+    fn len(&self) -> u64 {
+        self.tail - self.head
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Prefix of the keys of entries which can't be delivered, i.e. with a malformed key or an
+/// undecodable record. They are kept aside for inspection instead of being deleted. The byte
+/// can't appear in UTF-8, so no topic name starts with it.
+const QUARANTINE_PREFIX: u8 = 0xff;
+
+/// Durable FIFO of records that could not be published, one queue per topic.
+///
+/// Given a topic `T` and a sequence number `N`, {T}\0{N} is the key which maps to the
+/// bincode-encoded record. `N` is big-endian encoded, so keys of a topic are ordered
+/// the same way the records were queued.
+///
+/// The tail of a queue only moves past records which are persisted, so every sequence number
+/// between `head` and `tail` has its entry.
+pub struct Outbox {
+    db: Arc<rocksdb::DB>,
+    queues: Mutex<HashMap<String, Queue>>,
+    /// Held while a record is appended, from picking its sequence number to publishing it.
+    appending: AsyncMutex<()>,
+}
+
+impl Outbox {
+    pub fn new(db_path: &str) -> Result<Self, Error> {
+        let db = rocksdb::DB::open_default(db_path)?;
+        let mut queues: HashMap<String, Queue> = HashMap::new();
+        let mut malformed = Vec::new();
+        for entry in db.iterator(rocksdb::IteratorMode::Start) {
+            let (key, _) = entry?;
+            if key.first() == Some(&QUARANTINE_PREFIX) {
+                continue;
+            }
+            let Some((topic, seq)) = parse_key(&key) else {
+                malformed.push(key);
+                continue;
+            };
+            let queue = queues.entry(topic).or_insert(Queue {
+                head: seq,
+                tail: seq,
+            });
+            queue.head = queue.head.min(seq);
+            queue.tail = queue.tail.max(seq + 1);
+        }
+        for key in malformed {
+            warn!(target: "outbox", "Quarantining entry with malformed key {}", base16::encode_lower(&key));
+            quarantine(&db, &key)?;
+        }
+        Ok(Self {
+            db: Arc::new(db),
+            queues: Mutex::new(queues),
+            appending: AsyncMutex::new(()),
+        })
+    }
+
+    /// Total number of records awaiting delivery.
+    pub fn depth(&self) -> u64 {
+        self.queues.lock().unwrap().values().map(Queue::len).sum()
+    }
+
+    fn has_pending(&self, topic: &str) -> bool {
+        self.queues
+            .lock()
+            .unwrap()
+            .get(topic)
+            .is_some_and(|q| !q.is_empty())
+    }
+
+    /// Persists the record at the tail of its topic's queue. The record becomes visible to
+    /// [`Self::peek`] only once it's written, and a failed write leaves the queue as it was.
+    async fn push(&self, record: SinkRecord) -> Result<(), Error> {
+        let _appending = self.appending.lock().await;
+        let topic = record.topic.clone();
+        let seq = self
+            .queues
+            .lock()
+            .unwrap()
+            .get(&topic)
+            .map_or(0, |queue| queue.tail);
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            db.put(
+                make_key(&record.topic, seq),
+                bincode::serialize(&record).unwrap(),
+            )
+        })
+        .await?;
+        let mut queues = self.queues.lock().unwrap();
+        queues.entry(topic).or_default().tail = seq + 1;
+        Ok(())
+    }
+
+    /// Oldest record of the topic along with its sequence number. The record is `None` if it
+    /// was quarantined for failing to decode, so that it's skipped. A missing record fails the
+    /// read, leaving it to be retried.
+    async fn peek(&self, topic: &str) -> Result<Option<(u64, Option<SinkRecord>)>, Error> {
+        let seq = {
+            let queues = self.queues.lock().unwrap();
+            match queues.get(topic) {
+                Some(queue) if !queue.is_empty() => queue.head,
+                _ => return Ok(None),
+            }
+        };
+        let db = self.db.clone();
+        let key = make_key(topic, seq);
+        let record = spawn_blocking(move || -> Result<Option<SinkRecord>, Error> {
+            let Some(bytes) = db.get(&key)? else {
+                return Err(Error::MissingOutboxRecord(base16::encode_lower(&key)));
+            };
+            match bincode::deserialize(&bytes) {
+                Ok(record) => Ok(Some(record)),
+                Err(e) => {
+                    warn!(target: "outbox", "Quarantining undecodable record {}: {}", base16::encode_lower(&key), e);
+                    quarantine(&db, &key)?;
+                    Ok(None)
+                }
+            }
+        })
+        .await?;
+        Ok(Some((seq, record)))
+    }
+
+    async fn pop(&self, topic: &str, seq: u64) -> Result<(), Error> {
+        let db = self.db.clone();
+        let key = make_key(topic, seq);
+        spawn_blocking(move || db.delete(key)).await?;
+        if let Some(queue) = self.queues.lock().unwrap().get_mut(topic) {
+            queue.head = seq + 1;
+        }
+        Ok(())
+    }
+
+    fn topics(&self) -> Vec<String> {
+        self.queues.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Tries to deliver queued records topic by topic, oldest first. Delivery of a topic stops
+    /// at its first failure. Returns `true` if all queues were emptied.
+    async fn drain(&self, sink: &dyn EventSink) -> bool {
+        let mut drained = true;
+        for topic in self.topics() {
+            loop {
+                let (seq, record) = match self.peek(&topic).await {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!(target: "outbox", "Failed to read next record of [{}]: {}", topic, e);
+                        drained = false;
+                        break;
+                    }
+                };
+                if let Some(record) = record {
+                    if let Err(e) = sink.send(record).await {
+                        warn!(target: "outbox", "Failed to deliver record #{} to [{}]: {}", seq, topic, e);
+                        drained = false;
+                        break;
+                    }
+                }
+                if let Err(e) = self.pop(&topic, seq).await {
+                    warn!(target: "outbox", "Failed to remove record #{} of [{}]: {}", seq, topic, e);
+                    drained = false;
+                    break;
+                }
+            }
+        }
+        drained
+    }
+}
+
+fn make_key(topic: &str, seq: u64) -> Vec<u8> {
+    let mut key = topic.as_bytes().to_vec();
+    key.push(0);
+    key.extend_from_slice(&seq.to_be_bytes());
+    key
+}
+
+fn parse_key(key: &[u8]) -> Option<(String, u64)> {
+    let (topic, seq) = key.split_at(key.len().checked_sub(8)?);
+    let (&0, topic) = topic.split_last()? else {
+        return None;
+    };
+    let topic = String::from_utf8(topic.to_vec()).ok()?;
+    Some((topic, u64::from_be_bytes(seq.try_into().ok()?)))
+}
+
+/// Moves the entry under the given key aside, under [`QUARANTINE_PREFIX`].
+fn quarantine(db: &rocksdb::DB, key: &[u8]) -> Result<(), rocksdb::Error> {
+    if let Some(value) = db.get(key)? {
+        let mut quarantined_key = vec![QUARANTINE_PREFIX];
+        quarantined_key.extend_from_slice(key);
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put(quarantined_key, value);
+        batch.delete(key);
+        db.write(batch)?;
+    }
+    Ok(())
+}
+
+/// Publishes through the `inner` sink, diverting records which failed to publish into the
+/// [`Outbox`]. Once a topic has records in the outbox, subsequent records of that topic are
+/// queued behind them to preserve the per-topic order.
+pub struct OutboxSink {
+    inner: Arc<dyn EventSink>,
+    outbox: Arc<Outbox>,
+}
+
+impl OutboxSink {
+    pub fn new(inner: Arc<dyn EventSink>, outbox: Arc<Outbox>) -> Self {
+        Self { inner, outbox }
+    }
+}
+
+#[async_trait]
+impl EventSink for OutboxSink {
+    async fn send(&self, record: SinkRecord) -> Result<(), Error> {
+        if self.outbox.has_pending(&record.topic) {
+            return self.outbox.push(record).await;
+        }
+        if let Err(e) = self.inner.send(record.clone()).await {
+            warn!(target: "outbox", "Failed to publish record [{}] to [{}]: {}. Moving it to outbox", record.key, record.topic, e);
+            return self.outbox.push(record).await;
+        }
+        Ok(())
+    }
+
+    async fn send_batch(&self, records: Vec<SinkRecord>) -> Result<(), BatchError> {
+        let topics: HashSet<&str> = records.iter().map(|r| r.topic.as_str()).collect();
+        let pending_topics: HashSet<String> = topics
+            .into_iter()
+            .filter(|t| self.outbox.has_pending(t))
+            .map(String::from)
+            .collect();
+        let (mut queued, direct): (Vec<_>, Vec<_>) = records
+            .into_iter()
+            .partition(|r| pending_topics.contains(&r.topic));
+        if let Err(BatchError { error, undelivered }) = self.inner.send_batch(direct).await {
+            warn!(target: "outbox", "Failed to publish {} records: {}. Moving them to outbox", undelivered.len(), error);
+            queued.extend(undelivered);
+        }
+        for (ix, rec) in queued.iter().enumerate() {
+            if let Err(error) = self.outbox.push(rec.clone()).await {
+                return Err(BatchError::new(error, queued, ix));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Delivers records from the `outbox` through `sink` in the background. Retries are
+/// delayed with exponential backoff while deliveries keep failing. The outbox depth is
+/// reported under the `outbox_depth` target periodically.
+pub fn drain_outbox(
+    outbox: Arc<Outbox>,
+    sink: Arc<dyn EventSink>,
+    conf: OutboxConfig,
+) -> impl Stream<Item = ()> {
+    let initial_backoff = Duration::from_millis(conf.initial_backoff_ms);
+    let max_backoff = Duration::from_millis(conf.max_backoff_ms);
+    let report_interval = Duration::from_secs(conf.depth_report_interval_secs.unwrap_or(60));
+    stream::unfold(
+        (initial_backoff, None::<Instant>),
+        move |(backoff, last_report)| {
+            let outbox = outbox.clone();
+            let sink = sink.clone();
+            async move {
+                let _ = Delay::new(backoff).await;
+                let depth = outbox.depth();
+                let last_report = match last_report {
+                    Some(at) if at.elapsed() < report_interval => Some(at),
+                    _ => {
+                        info!(target: "outbox_depth", "depth={}", depth);
+                        Some(Instant::now())
+                    }
+                };
+                if depth == 0 {
+                    return Some(((), (initial_backoff, last_report)));
+                }
+                warn!(target: "outbox", "Outbox depth: {}", depth);
+                let next_backoff = if outbox.drain(&*sink).await {
+                    info!(target: "outbox", "Outbox drained");
+                    initial_backoff
+                } else {
+                    (backoff * 2).min(max_backoff)
+                };
+                Some(((), (next_backoff, last_report)))
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use async_trait::async_trait;
+
+    use crate::sink::memory::InMemorySink;
+    use crate::sink::outbox::{make_key, Outbox, OutboxSink};
+    use crate::sink::{Error, EventSink, SinkRecord};
+
+    /// Sink rejecting records of the given topics.
+    struct FailingSink {
+        inner: InMemorySink,
+        failing_topics: Vec<&'static str>,
+    }
+
+    #[async_trait]
+    impl EventSink for FailingSink {
+        async fn send(&self, record: SinkRecord) -> Result<(), Error> {
+            if self.failing_topics.contains(&record.topic.as_str()) {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "unavailable",
+                )));
+            }
+            self.inner.send(record).await
+        }
+    }
+
+    fn db_path(name: &str) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        format!("./tmp/outbox-{}-{}", name, nanos)
+    }
+
+    fn keys(records: &[SinkRecord], topic: &str) -> Vec<String> {
+        records
+            .iter()
+            .filter(|r| r.topic == topic)
+            .map(|r| r.key.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn records_are_delivered_in_fifo_order_per_topic() {
+        let outbox = Arc::new(Outbox::new(&db_path("fifo")).unwrap());
+        let sink = OutboxSink::new(
+            Arc::new(FailingSink {
+                inner: InMemorySink::new(),
+                failing_topics: vec!["a", "b"],
+            }),
+            outbox.clone(),
+        );
+        for (topic, key) in [("a", "a1"), ("b", "b1"), ("a", "a2"), ("b", "b2")] {
+            sink.send(SinkRecord::new(topic, key, vec![]))
+                .await
+                .unwrap();
+        }
+        assert_eq!(outbox.depth(), 4);
+
+        let target = InMemorySink::new();
+        assert!(outbox.drain(&target).await);
+        assert_eq!(outbox.depth(), 0);
+        let records = target.records();
+        assert_eq!(keys(&records, "a"), vec!["a1", "a2"]);
+        assert_eq!(keys(&records, "b"), vec!["b1", "b2"]);
+    }
+
+    #[tokio::test]
+    async fn queued_records_are_drained_after_reopen() {
+        let path = db_path("reopen");
+        {
+            let outbox = Arc::new(Outbox::new(&path).unwrap());
+            let sink = OutboxSink::new(
+                Arc::new(FailingSink {
+                    inner: InMemorySink::new(),
+                    failing_topics: vec!["a"],
+                }),
+                outbox.clone(),
+            );
+            for key in ["a1", "a2", "a3"] {
+                sink.send(SinkRecord::new("a", key, vec![])).await.unwrap();
+            }
+            // Entries the outbox can't make sense of are skipped rather than fatal.
+            outbox.db.put(b"garbage", b"").unwrap();
+            outbox.db.put(make_key("a", 3), b"garbage").unwrap();
+        }
+
+        let outbox = Outbox::new(&path).unwrap();
+        assert_eq!(outbox.depth(), 4);
+        let target = InMemorySink::new();
+        assert!(outbox.drain(&target).await);
+        assert_eq!(keys(&target.records(), "a"), vec!["a1", "a2", "a3"]);
+        assert_eq!(outbox.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn batches_are_split_between_sink_and_outbox() {
+        let outbox = Arc::new(Outbox::new(&db_path("batch")).unwrap());
+        let inner = InMemorySink::new();
+        let sink = OutboxSink::new(
+            Arc::new(FailingSink {
+                inner: inner.clone(),
+                failing_topics: vec!["b"],
+            }),
+            outbox.clone(),
+        );
+        // Delivery stops at the first failure, so everything from `b1` on is queued.
+        let batch = vec![
+            SinkRecord::new("a", "a1", vec![]),
+            SinkRecord::new("b", "b1", vec![]),
+            SinkRecord::new("a", "a2", vec![]),
+        ];
+        sink.send_batch(batch).await.unwrap();
+        assert_eq!(outbox.depth(), 2);
+        // Topic `a` has queued records now, so its new records are queued behind them.
+        let batch = vec![
+            SinkRecord::new("a", "a3", vec![]),
+            SinkRecord::new("c", "c1", vec![]),
+        ];
+        sink.send_batch(batch).await.unwrap();
+        assert_eq!(outbox.depth(), 3);
+        let records = inner.records();
+        assert_eq!(keys(&records, "a"), vec!["a1"]);
+        assert_eq!(keys(&records, "c"), vec!["c1"]);
+
+        let target = InMemorySink::new();
+        assert!(outbox.drain(&target).await);
+        let records = target.records();
+        assert_eq!(keys(&records, "a"), vec!["a2", "a3"]);
+        assert_eq!(keys(&records, "b"), vec!["b1"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn records_pushed_while_draining_are_delivered_in_order() {
+        let outbox = Arc::new(Outbox::new(&db_path("concurrent")).unwrap());
+        let sent: Vec<String> = (0..200).map(|i| format!("a{}", i)).collect();
+        let pushing = {
+            let outbox = outbox.clone();
+            let sent = sent.clone();
+            tokio::spawn(async move {
+                for key in sent {
+                    outbox
+                        .push(SinkRecord::new("a", key, vec![]))
+                        .await
+                        .unwrap();
+                }
+            })
+        };
+        // Records are drained as soon as they are visible, none of them may be found missing.
+        let target = InMemorySink::new();
+        while !pushing.is_finished() {
+            assert!(outbox.drain(&target).await);
+            tokio::task::yield_now().await;
+        }
+        pushing.await.unwrap();
+        assert!(outbox.drain(&target).await);
+        assert_eq!(keys(&target.records(), "a"), sent);
+    }
+
+    #[tokio::test]
+    async fn missing_records_are_not_skipped() {
+        let outbox = Outbox::new(&db_path("missing")).unwrap();
+        for key in ["a1", "a2"] {
+            outbox
+                .push(SinkRecord::new("a", key, vec![]))
+                .await
+                .unwrap();
+        }
+        outbox.db.delete(make_key("a", 0)).unwrap();
+
+        let target = InMemorySink::new();
+        assert!(!outbox.drain(&target).await);
+        assert!(target.records().is_empty());
+        assert_eq!(outbox.depth(), 2);
+    }
+}
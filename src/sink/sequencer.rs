@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_std::task::spawn_blocking;
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::sink::{SinkRecord, SEQ_HEADER};
+
+/// Hands out per-topic sequence numbers.
+#[async_trait]
+pub trait Sequencer: Send + Sync {
+    /// Reserves `n` consecutive sequence numbers of the given `topic`, returning the first one.
+    async fn reserve(&self, topic: &str, n: u64) -> u64;
+
+    /// Stamps every record with the next sequence number of its topic, in order.
+    async fn stamp(&self, records: &mut [SinkRecord]) {
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for rec in records.iter() {
+            *counts.entry(rec.topic.clone()).or_default() += 1;
+        }
+        let mut next: HashMap<String, u64> = HashMap::new();
+        for (topic, n) in counts {
+            let first = self.reserve(&topic, n).await;
+            next.insert(topic, first);
+        }
+        for rec in records.iter_mut() {
+            let seq = next.get_mut(&rec.topic).unwrap();
+            rec.headers.push((SEQ_HEADER.to_string(), seq.to_string()));
+            *seq += 1;
+        }
+    }
+}
+
+/// Keeps the next sequence number of a topic `T` under {SEQUENCE:T} key. Numbers are persisted
+/// before they are handed out, so they keep increasing across restarts. Records which are
+/// published again after a restart are given new numbers.
+pub struct SequencerRocksDB {
+    db: Arc<rocksdb::OptimisticTransactionDB>,
+    next: Mutex<HashMap<String, u64>>,
+}
+
+impl SequencerRocksDB {
+    pub fn new(db: Arc<rocksdb::OptimisticTransactionDB>) -> Self {
+        Self {
+            db,
+            next: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl Sequencer for SequencerRocksDB {
+    async fn reserve(&self, topic: &str, n: u64) -> u64 {
+        // Held until the new value is persisted, so that concurrent reservations can't overlap.
+        let mut next = self.next.lock().await;
+        let key = bincode::serialize(&format!("SEQUENCE:{}", topic)).unwrap();
+        let first = match next.get(topic) {
+            Some(seq) => *seq,
+            None => {
+                let db = self.db.clone();
+                let key = key.clone();
+                spawn_blocking(move || {
+                    db.get(key)
+                        .unwrap()
+                        .and_then(|bytes| bincode::deserialize(&bytes).ok())
+                        .unwrap_or(0)
+                })
+                .await
+            }
+        };
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            db.put(key, bincode::serialize(&(first + n)).unwrap())
+                .unwrap()
+        })
+        .await;
+        next.insert(topic.to_string(), first + n);
+        first
+    }
+}
+
+#[derive(Default)]
+pub struct InMemorySequencer {
+    next: Mutex<HashMap<String, u64>>,
+}
+
+impl InMemorySequencer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Sequencer for InMemorySequencer {
+    async fn reserve(&self, topic: &str, n: u64) -> u64 {
+        let mut next = self.next.lock().await;
+        let seq = next.entry(topic.to_string()).or_default();
+        *seq += n;
+        *seq - n
+    }
+}
@@ -0,0 +1,37 @@
+use std::io::Write;
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::{json, Map, Value};
+
+use crate::sink::{Error, EventSink, SinkRecord};
+
+/// Writes every record to stdout as a single line of JSON.
+pub struct StdoutSink;
+
+#[async_trait]
+impl EventSink for StdoutSink {
+    async fn send(&self, record: SinkRecord) -> Result<(), Error> {
+        // Inline JSON payloads so that the output stays `jq`-friendly. Binary payloads are
+        // written base64-encoded, tombstones as null.
+        let value = match &record.value {
+            Some(bytes) => serde_json::from_slice::<Value>(bytes)
+                .unwrap_or_else(|_| Value::String(general_purpose::STANDARD.encode(bytes))),
+            None => Value::Null,
+        };
+        let headers: Map<String, Value> = record
+            .headers
+            .into_iter()
+            .map(|(name, value)| (name, Value::String(value)))
+            .collect();
+        let line = json!({
+            "topic": record.topic,
+            "key": record.key,
+            "headers": headers,
+            "value": value,
+        });
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", line)?;
+        Ok(())
+    }
+}